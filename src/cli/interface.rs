@@ -1,4 +1,4 @@
-use clap::{Arg, ArgMatches, Command};
+use clap::{Arg, ArgAction, ArgMatches, Command};
 
 /// Prints an ASCII art banner to look cool!
 pub fn banner() {
@@ -11,11 +11,12 @@ pub fn args() -> ArgMatches {
         .version(env!("CARGO_PKG_VERSION"))
         .author("Mufeed VH <mufeed@lyminal.space>")
         .about("A fast static web server with Automatic HTTPs, routing, templating, and security in a single binary you can setup with zero code.")
-        .arg(Arg::new("command")
-            .help("Command to run.")
-            .value_name("COMMAND")
-            .required(false)
-            .index(1))
+        // `-h` is taken by `--host`
+        .disable_help_flag(true)
+        .arg(Arg::new("help")
+            .long("help")
+            .help("Print help.")
+            .action(ArgAction::Help))
         .arg(Arg::new("host")
             .short('h')
             .long("host")
@@ -34,5 +35,10 @@ pub fn args() -> ArgMatches {
             .value_name("TLS CERT")
             .help("TLS cert file.")
             .required(false))
+        .subcommand(Command::new("cert")
+            .about("Manage TLS certificates.")
+            .subcommand_required(true)
+            .subcommand(Command::new("dev")
+                .about("Create a certificate for local development, signed by a local CA.")))
         .get_matches()
 }
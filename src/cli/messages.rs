@@ -2,7 +2,7 @@ use colored::*;
 
 /// CLI message types
 pub enum Type {
-    _Warning,
+    Warning,
     _Skipped,
     Error,
     Info,
@@ -13,7 +13,7 @@ pub enum Type {
 // NOTE: this is not logging, just meant to be messages for the CLI user
 pub fn push_message(log_type: Type, message: &str) {
     let prefix = match log_type {
-        Type::_Warning => format!("{}{}{}", "[".bold(), "WARN".bold().yellow(), "]".bold()),
+        Type::Warning => format!("{}{}{}", "[".bold(), "WARN".bold().yellow(), "]".bold()),
         Type::_Skipped => format!("{}{}{}", "[".bold(), "SKIPPED".bold().yellow(), "]".bold()),
         Type::Error => format!("{}{}{}", "[".bold(), "ERROR".bold().red(), "]".bold()),
         Type::Info => format!("{}{}{}", "[".bold(), "INFO".bold().cyan(), "]".bold()),
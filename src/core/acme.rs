@@ -0,0 +1,442 @@
+use actix_web::{
+    rt::{task::spawn_blocking, time::sleep},
+    web::Bytes,
+};
+
+use instant_acme::{
+    Account, AccountCredentials, AuthorizationStatus, ChallengeType, HttpClient, Identifier,
+    NewAccount, NewOrder, Order, OrderStatus,
+};
+
+use http_body_util::Full;
+use hyper_rustls::HttpsConnectorBuilder;
+use hyper_util::{client::legacy::Client as HyperClient, rt::TokioExecutor};
+use rustls_0_23::{ClientConfig, RootCertStore};
+
+use rcgen::{CertificateParams, DistinguishedName, KeyPair};
+
+use x509_parser::{extensions::GeneralName, pem::parse_x509_pem};
+
+use dashmap::DashMap;
+use once_cell::sync::Lazy;
+
+use compact_str::CompactString;
+
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+
+use ahash::RandomState;
+
+use super::config::{Acme, Ocsp, CONFIG_STATE};
+use super::files::write_private;
+use super::tls::{certified_key_from_pem, read_ca_certificates, staple_ocsp, CERT_RESOLVER};
+
+use crate::cli::messages::{push_message, Type};
+
+/// Path prefix of the HTTP-01 challenge requests (RFC 8555 §8.3)
+pub const CHALLENGE_PREFIX: &str = "/.well-known/acme-challenge/";
+
+/// How often the certificate is checked for renewal
+const CHECK_INTERVAL: Duration = Duration::from_secs(12 * 60 * 60);
+
+/// How long to wait before retrying a failed issuance
+const RETRY_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+/// Pending HTTP-01 challenges, token -> key authorization
+static CHALLENGES: Lazy<DashMap<CompactString, CompactString, RandomState>> =
+    Lazy::new(|| DashMap::with_hasher(RandomState::new()));
+
+/// Files persisted in the ACME state directory
+const ACCOUNT_FILE: &str = "account.json";
+const CERT_FILE: &str = "certificate.pem";
+const KEY_FILE: &str = "private_key.pem";
+
+/// Returns the key authorization for a pending HTTP-01 challenge request path.
+pub fn challenge_response(path: &str) -> Option<CompactString> {
+    let token = path.strip_prefix(CHALLENGE_PREFIX)?;
+
+    CHALLENGES
+        .get(token)
+        .map(|key_auth| key_auth.value().to_owned())
+}
+
+/// Issue the certificate if needed and keep renewing it in the background.
+pub async fn renew_certificates() {
+    loop {
+        let (acme, ocsp) = {
+            let config = CONFIG_STATE.lock();
+            (
+                config.server.tls.acme.to_owned(),
+                config.server.tls.ocsp.to_owned(),
+            )
+        };
+
+        // the stapled OCSP response is refreshed along with the check
+        let check_interval = if ocsp.enable {
+            CHECK_INTERVAL.min(Duration::from_secs(ocsp.refresh_interval))
+        } else {
+            CHECK_INTERVAL
+        };
+
+        match ensure_certificate(&acme, &ocsp).await {
+            Ok(()) => sleep(check_interval).await,
+            Err(e) => {
+                push_message(
+                    Type::Error,
+                    &format!("ACME certificate issuance failed: {:#}", e),
+                );
+                sleep(RETRY_INTERVAL).await
+            }
+        }
+    }
+}
+
+/// Make sure a valid certificate for the configured domains is being served.
+async fn ensure_certificate(acme: &Acme, ocsp: &Ocsp) -> Result<()> {
+    if acme.domains.is_empty() {
+        bail!("No domains configured in `server.tls.acme.domains`");
+    }
+
+    let state_directory = &acme.state_directory;
+    let cert_path = state_directory.join(CERT_FILE);
+    let key_path = state_directory.join(KEY_FILE);
+
+    // serve the stored certificate, until it's about to expire
+    if let (Ok(cert_pem), Ok(key_pem)) = (fs::read(&cert_path), fs::read(&key_path)) {
+        serve_certificate(&cert_pem, &key_pem, &cert_path, ocsp).await?;
+
+        if !needs_renewal(&cert_pem, acme)? {
+            return Ok(());
+        }
+    }
+
+    push_message(
+        Type::Info,
+        &format!(
+            "Requesting ACME certificate for {}",
+            acme.domains.join(", ")
+        ),
+    );
+
+    let account = load_or_create_account(acme).await?;
+    let (cert_pem, key_pem) = order_certificate(&account, &acme.domains).await?;
+
+    serve_certificate(cert_pem.as_bytes(), key_pem.as_bytes(), &cert_path, ocsp).await?;
+
+    fs::write(&cert_path, &cert_pem)?;
+    write_private(&key_path, &key_pem)?;
+
+    push_message(Type::Success, "ACME certificate issued 🔒");
+
+    Ok(())
+}
+
+/// Serve a certificate for the new TLS handshakes, with its OCSP response stapled. (`tls.ocsp`)
+async fn serve_certificate(
+    cert_pem: &[u8],
+    key_pem: &[u8],
+    cert_path: &Path,
+    ocsp: &Ocsp,
+) -> Result<()> {
+    let mut certified_key = certified_key_from_pem(cert_pem, key_pem)?;
+
+    if ocsp.enable {
+        let previous = CERT_RESOLVER.default_certificate();
+        let (cert_path, ocsp) = (cert_path.to_owned(), ocsp.to_owned());
+
+        // the OCSP request is blocking
+        certified_key = spawn_blocking(move || {
+            staple_ocsp(
+                &mut certified_key,
+                &cert_path,
+                Path::new(""),
+                &ocsp,
+                previous,
+            );
+
+            certified_key
+        })
+        .await?;
+    }
+
+    CERT_RESOLVER.set(certified_key);
+
+    Ok(())
+}
+
+/// Check the expiry and the domains covered by the stored certificate.
+fn needs_renewal(cert_pem: &[u8], acme: &Acme) -> Result<bool> {
+    let (_, pem) = parse_x509_pem(cert_pem).context("Failed to parse ACME certificate")?;
+    let cert = pem.parse_x509()?;
+
+    // reissue if the configured domains changed
+    let mut names = Vec::new();
+    if let Some(san) = cert.subject_alternative_name()? {
+        for name in &san.value.general_names {
+            if let GeneralName::DNSName(dns_name) = name {
+                names.push(*dns_name);
+            }
+        }
+    }
+
+    if acme
+        .domains
+        .iter()
+        .any(|domain| !names.contains(&domain.as_str()))
+    {
+        return Ok(true);
+    }
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+    let renew_before = (acme.renew_before_days * 24 * 60 * 60) as i64;
+
+    Ok(cert.validity().not_after.timestamp() - now < renew_before)
+}
+
+/// HTTP client of the ACME server, trusting the `ca_root` certificates (like the CA of
+/// a local test server such as Pebble) instead of the system roots if configured.
+fn http_client(acme: &Acme) -> Result<Box<dyn HttpClient>> {
+    let connector = match acme.ca_root.as_os_str().is_empty() {
+        true => HttpsConnectorBuilder::new().with_native_roots()?,
+        false => HttpsConnectorBuilder::new().with_tls_config(ca_root_config(&acme.ca_root)?),
+    };
+
+    let connector = connector.https_only().enable_http1().enable_http2().build();
+
+    Ok(Box::new(
+        HyperClient::builder(TokioExecutor::new()).build::<_, Full<Bytes>>(connector),
+    ))
+}
+
+/// TLS client configuration trusting only the certificates of a CA bundle.
+fn ca_root_config(ca_path: &Path) -> Result<ClientConfig> {
+    let mut roots = RootCertStore::empty();
+    for cert in read_ca_certificates(ca_path)? {
+        roots.add(cert)?;
+    }
+
+    Ok(ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth())
+}
+
+/// Restore the ACME account from the state directory or register a new one.
+async fn load_or_create_account(acme: &Acme) -> Result<Account> {
+    let account_path = acme.state_directory.join(ACCOUNT_FILE);
+
+    if let Ok(credentials) = fs::read(&account_path) {
+        let credentials: AccountCredentials = serde_json::from_slice(&credentials)?;
+        return Ok(Account::from_credentials_and_http(credentials, http_client(acme)?).await?);
+    }
+
+    let contact = acme
+        .contact
+        .iter()
+        .map(|contact| {
+            if contact.starts_with("mailto:") {
+                contact.to_owned()
+            } else {
+                format!("mailto:{}", contact)
+            }
+        })
+        .collect::<Vec<String>>();
+    let contact = contact.iter().map(String::as_str).collect::<Vec<&str>>();
+
+    let (account, credentials) = Account::create_with_http(
+        &NewAccount {
+            contact: &contact,
+            terms_of_service_agreed: true,
+            only_return_existing: false,
+        },
+        &acme.directory,
+        None,
+        http_client(acme)?,
+    )
+    .await?;
+
+    fs::create_dir_all(&acme.state_directory)?;
+    write_private(&account_path, &serde_json::to_string(&credentials)?)?;
+
+    Ok(account)
+}
+
+/// Complete an order with HTTP-01 challenges, returns the certificate chain and key.
+async fn order_certificate(account: &Account, domains: &[String]) -> Result<(String, String)> {
+    let identifiers = domains
+        .iter()
+        .map(|domain| Identifier::Dns(domain.to_owned()))
+        .collect::<Vec<Identifier>>();
+
+    let mut order = account
+        .new_order(&NewOrder {
+            identifiers: &identifiers,
+        })
+        .await?;
+
+    let mut tokens = Vec::new();
+
+    let result = complete_order(&mut order, domains, &mut tokens).await;
+
+    // challenges are single use
+    for token in tokens {
+        CHALLENGES.remove(&token);
+    }
+
+    result
+}
+
+async fn complete_order(
+    order: &mut Order,
+    domains: &[String],
+    tokens: &mut Vec<CompactString>,
+) -> Result<(String, String)> {
+    for authorization in order.authorizations().await? {
+        match authorization.status {
+            AuthorizationStatus::Pending => {}
+            AuthorizationStatus::Valid => continue,
+            status => bail!("Unexpected ACME authorization status: {:?}", status),
+        }
+
+        let challenge = authorization
+            .challenges
+            .iter()
+            .find(|challenge| challenge.r#type == ChallengeType::Http01)
+            .context("ACME server offered no HTTP-01 challenge")?;
+
+        // publish the key authorization for the `router` to answer
+        let key_authorization = order.key_authorization(challenge);
+        let token = CompactString::from(challenge.token.as_str());
+        CHALLENGES.insert(token.to_owned(), key_authorization.as_str().into());
+        tokens.push(token);
+
+        order.set_challenge_ready(&challenge.url).await?;
+    }
+
+    // wait for the ACME server to validate the challenges
+    let state = poll_order(order, |status| status != OrderStatus::Pending).await?;
+    if state != OrderStatus::Ready {
+        bail!("ACME order failed: {:?}", order.state().error);
+    }
+
+    let key_pair = KeyPair::generate()?;
+    let mut params = CertificateParams::new(domains.to_vec())?;
+    params.distinguished_name = DistinguishedName::new();
+    let csr = params.serialize_request(&key_pair)?;
+
+    order.finalize(csr.der()).await?;
+
+    let state = poll_order(order, |status| status != OrderStatus::Processing).await?;
+    if state != OrderStatus::Valid {
+        bail!("ACME order finalization failed: {:?}", order.state().error);
+    }
+
+    let cert_chain = order
+        .certificate()
+        .await?
+        .context("ACME server returned no certificate")?;
+
+    Ok((cert_chain, key_pair.serialize_pem()))
+}
+
+/// Refresh the order state with backoff until `done` returns true.
+async fn poll_order(order: &mut Order, done: impl Fn(OrderStatus) -> bool) -> Result<OrderStatus> {
+    let mut delay = Duration::from_millis(250);
+
+    for _ in 0..10 {
+        let status = order.refresh().await?.status;
+
+        if done(status) {
+            return Ok(status);
+        }
+
+        sleep(delay).await;
+        delay *= 2;
+    }
+
+    bail!("Timed out waiting for the ACME order")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use actix_web::{web, App, HttpResponse, HttpServer};
+
+    use rcgen::Certificate;
+
+    use rustls::pki_types::{CertificateDer, PrivatePkcs8KeyDer};
+
+    use crate::core::test_utils::{private_ca, write_temp};
+
+    /// Serve an ACME directory over HTTPS with a certificate issued by the CA, returns the URL.
+    fn serve_directory(ca: &Certificate, ca_key: &KeyPair) -> String {
+        let key_pair = KeyPair::generate().unwrap();
+        let cert = CertificateParams::new(vec!["localhost".into()])
+            .unwrap()
+            .signed_by(&key_pair, ca, ca_key)
+            .unwrap();
+
+        let config = rustls::ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![CertificateDer::from(cert.der().to_vec())],
+                PrivatePkcs8KeyDer::from(key_pair.serialize_der()).into(),
+            )
+            .unwrap();
+
+        let server = HttpServer::new(|| {
+            App::new().route(
+                "/dir",
+                web::get().to(|| async { HttpResponse::Ok().json(serde_json::json!({})) }),
+            )
+        })
+        .workers(1)
+        .bind_rustls_0_22(("127.0.0.1", 0), config)
+        .unwrap();
+
+        let port = server.addrs()[0].port();
+        actix_web::rt::spawn(server.run());
+
+        format!("https://localhost:{}/dir", port)
+    }
+
+    async fn fetch(acme: &Acme) -> Result<http::StatusCode> {
+        let request = http::Request::get(&acme.directory).body(Full::default())?;
+        let response = http_client(acme)?.request(request).await?;
+
+        Ok(response.parts.status)
+    }
+
+    #[actix_web::test]
+    async fn trusts_the_ca_root() {
+        let (ca, ca_key) = private_ca();
+        let directory = serve_directory(&ca, &ca_key);
+
+        let ca_root = write_temp("ca-root.pem", ca.pem());
+        let acme = Acme {
+            directory: directory.to_owned(),
+            ca_root: ca_root.0.to_owned(),
+            ..Acme::default()
+        };
+        assert_eq!(fetch(&acme).await.unwrap(), http::StatusCode::OK);
+
+        // a server with a certificate of any other CA isn't trusted
+        let (other_ca, _) = private_ca();
+        let other_ca_root = write_temp("other-ca-root.pem", other_ca.pem());
+        let acme = Acme {
+            directory,
+            ca_root: other_ca_root.0.to_owned(),
+            ..Acme::default()
+        };
+        assert!(fetch(&acme).await.is_err());
+    }
+
+    #[test]
+    fn rejects_a_ca_root_without_certificates() {
+        let ca_root = write_temp("empty-ca-root.pem", "");
+        assert!(ca_root_config(&ca_root.0).is_err());
+    }
+}
@@ -0,0 +1,206 @@
+use ahash::RandomState;
+use compact_str::CompactString;
+use dashmap::DashMap;
+use once_cell::sync::{Lazy, OnceCell};
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::Result;
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+
+use jwalk::WalkDir;
+
+use ring::digest::{Context, SHA384};
+
+use xxhash_rust::xxh3::Xxh3;
+
+use super::config::{Config, Static};
+use super::files;
+
+/// Length of the content hash in the fingerprinted file names
+const HASH_LENGTH: usize = 8;
+
+/// A file of the `static` directory
+#[derive(Debug)]
+pub struct Asset {
+    pub path: PathBuf,     // path to the file in disk
+    pub url: String,       // URL of the file, fingerprinted if `static.fingerprint` is enabled
+    pub plain_url: String, // URL of the file under it's own name
+    pub fingerprinted: bool,
+    pub minify: bool, // minified at build time with `minify_css`/`minify_js`
+    integrity: OnceCell<String>, // the Subresource Integrity metadata, see `integrity()`
+}
+
+impl Asset {
+    /// The URLs of the file which are routes, the rest are left to the static file service.
+    pub fn routes(&self) -> impl Iterator<Item = &str> {
+        let fingerprinted = self.fingerprinted.then_some(self.url.as_str());
+        let minified = self.minify.then_some(self.plain_url.as_str());
+
+        fingerprinted.into_iter().chain(minified)
+    }
+
+    /// The Subresource Integrity metadata of the file, `sha384-` and the base64 digest of it's contents.
+    ///
+    /// It's hashed once per build of the assets, the first time a template asks for it.
+    pub fn integrity(&self) -> io::Result<&str> {
+        self.integrity
+            .get_or_try_init(|| integrity(self))
+            .map(String::as_str)
+    }
+}
+
+/// The files of a `static` directory by their path relative to it (e.g. `css/styles.css`)
+pub type Assets = HashMap<String, Asset>;
+
+/// The static files of each site
+pub static ASSETS: Lazy<DashMap<CompactString, Arc<Assets>, RandomState>> =
+    Lazy::new(|| DashMap::with_hasher(RandomState::new()));
+
+/// The assets of a site, as of the last build.
+pub fn site_assets(site_name: &str) -> Arc<Assets> {
+    ASSETS
+        .get(site_name)
+        .map(|assets| assets.value().clone())
+        .unwrap_or_default()
+}
+
+/// Collect the files of the `static` directory and fingerprint them if configured,
+/// `css/styles.css` is served as `/assets/css/styles.1a2b3c4d.css` as well.
+pub fn collect(static_config: &Static, config: &Config) -> Result<Assets> {
+    let mut assets = Assets::new();
+
+    let directory = &static_config.directory;
+    let served_from = static_config.served_from.trim_end_matches('/');
+
+    if static_config.served_from.is_empty() || directory == &PathBuf::new() {
+        return Ok(assets);
+    }
+
+    for entry in WalkDir::new(directory).follow_links(config.follow_symlinks) {
+        let entry = entry?;
+        let path = entry.path();
+
+        // the precompressed sidecars are served along with their file
+        if !entry.file_type().is_file() || files::is_sidecar(&path) {
+            continue;
+        }
+
+        let Ok(relative_path) = path.strip_prefix(directory) else {
+            continue;
+        };
+
+        let name = relative_path
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+
+        let mime_type = new_mime_guess::from_path(&path)
+            .first_raw()
+            .unwrap_or("application/octet-stream");
+
+        let url_name = match static_config.fingerprint {
+            true => fingerprinted_name(&name, content_hash(&path)?),
+            false => name.to_owned(),
+        };
+
+        assets.insert(
+            name.to_owned(),
+            Asset {
+                url: format!("{}/{}", served_from, url_name),
+                plain_url: format!("{}/{}", served_from, name),
+                fingerprinted: static_config.fingerprint,
+                minify: files::should_minify(&path, mime_type, config),
+                integrity: OnceCell::new(),
+                path,
+            },
+        );
+    }
+
+    Ok(assets)
+}
+
+/// Find an asset by it's path in the `static` directory (`css/styles.css`)
+/// or by any of the URLs it's served at (`/assets/css/styles.css`).
+pub fn find<'a>(assets: &'a Assets, reference: &str) -> Option<&'a Asset> {
+    let reference = reference.trim_start_matches('/');
+
+    assets.get(reference).or_else(|| {
+        assets.values().find(|asset| {
+            asset.url.trim_start_matches('/') == reference
+                || asset.plain_url.trim_start_matches('/') == reference
+        })
+    })
+}
+
+/// Hash a file for its Subresource Integrity metadata, as it's served.
+fn integrity(asset: &Asset) -> io::Result<String> {
+    let mut context = Context::new(&SHA384);
+
+    if asset.minify {
+        // hashed as it's served, the file is served unchanged if it fails to minify
+        let contents = fs::read(&asset.path)?;
+        let mime_type = new_mime_guess::from_path(&asset.path)
+            .first_raw()
+            .unwrap_or("application/octet-stream");
+
+        match files::minify(&asset.path, mime_type, &contents) {
+            Ok(minified) => context.update(&minified),
+            Err(_) => context.update(&contents),
+        }
+    } else {
+        read_chunks(&asset.path, |chunk| context.update(chunk))?;
+    }
+
+    Ok(format!("sha384-{}", BASE64.encode(context.finish())))
+}
+
+/// Insert the hash before the extension, `css/styles.css` becomes `css/styles.1a2b3c4d.css`.
+fn fingerprinted_name(name: &str, hash: u64) -> String {
+    let hash = format!("{:016x}", hash);
+    let hash = &hash[..HASH_LENGTH];
+
+    let (directory, file_name) = name.rsplit_once('/').unwrap_or(("", name));
+
+    let file_name = match file_name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => {
+            format!("{}.{}.{}", stem, hash, extension)
+        }
+        _ => format!("{}.{}", file_name, hash),
+    };
+
+    match directory.is_empty() {
+        true => file_name,
+        false => format!("{}/{}", directory, file_name),
+    }
+}
+
+/// Hash of the contents of a file.
+fn content_hash(path: &Path) -> io::Result<u64> {
+    let mut hasher = Xxh3::new();
+    read_chunks(path, |chunk| hasher.update(chunk))?;
+
+    Ok(hasher.digest())
+}
+
+/// Read a file in chunks, the static files can be large.
+pub fn read_chunks(path: &Path, mut f: impl FnMut(&[u8])) -> io::Result<()> {
+    let mut file = File::open(path)?;
+    let mut buffer = vec![0; 65_536];
+
+    loop {
+        let read = file.read(&mut buffer)?;
+
+        if read == 0 {
+            return Ok(());
+        }
+
+        f(&buffer[..read]);
+    }
+}
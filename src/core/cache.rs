@@ -0,0 +1,396 @@
+use actix_web::{rt::task, web::Bytes};
+
+use ahash::RandomState;
+use compact_str::CompactString;
+use dashmap::{DashMap, DashSet};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde::Serialize;
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::files::StaticFile;
+use super::routes::{RouteHandle, Type, ROUTEMAP};
+
+use crate::cli::messages::{push_message, Type as MsgType};
+
+/// Misses of a route between the attempts to promote it into memory
+const PROMOTE_AFTER: u64 = 3;
+
+/// Requests between halving the request counts of the routes,
+/// so the formerly hot routes cool down and can be evicted.
+const AGING_PERIOD: u64 = 10_000;
+
+/// The in-memory cache of the routes, bound by `cache.max_memory`.
+///
+/// Routes start out being served from disk (`Type::File`), the most requested
+/// ones are promoted into memory (`Type::Bytes`) evicting the less requested
+/// ones when the memory budget runs out.
+pub static CACHE: Lazy<MemoryCache> = Lazy::new(MemoryCache::default);
+
+#[derive(Default)]
+pub struct MemoryCache {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    promotions: AtomicU64,
+    evictions: AtomicU64,
+    max_memory: AtomicU64, // 0 if there is no memory budget
+    requests: DashMap<CompactString, u64, RandomState>, // request counts of the routes
+    promoting: DashSet<CompactString, RandomState>,
+    resident: Mutex<Resident>,
+}
+
+/// The routes promoted into memory
+#[derive(Default)]
+struct Resident {
+    routes: HashMap<CompactString, u64>, // route key -> bytes of memory taken
+    pinned: HashMap<CompactString, u64>, // the routes which are always in memory (e.g. templates)
+    used_memory: u64,
+}
+
+/// Cache statistics, served at `cache.stats_route`
+#[derive(Serialize)]
+pub struct Stats {
+    pub hits: u64,
+    pub misses: u64,
+    pub promotions: u64,
+    pub evictions: u64,
+    pub cached_routes: usize,
+    pub used_memory: u64,
+    pub max_memory: Option<u64>,
+}
+
+impl MemoryCache {
+    /// Start over with a new memory budget, the routes have been created again.
+    pub fn reset(&self, max_memory: Option<u64>) {
+        let mut resident = self.resident.lock();
+
+        self.max_memory
+            .store(max_memory.unwrap_or_default(), Ordering::Relaxed);
+
+        resident.routes.clear();
+        resident.pinned.clear();
+        resident.used_memory = 0;
+    }
+
+    /// Record a request served from memory.
+    pub fn hit(&self, key: &str) {
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        self.count(key);
+    }
+
+    /// Record a request served from disk, the route gets promoted into memory
+    /// if it's requested often enough.
+    pub fn miss(&self, key: &str) {
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let count = self.count(key);
+
+        let max_memory = self.max_memory.load(Ordering::Relaxed);
+
+        if max_memory == 0
+            || !count.is_multiple_of(PROMOTE_AFTER)
+            || !self.promoting.insert(key.into())
+        {
+            return;
+        }
+
+        let key = CompactString::from(key);
+
+        // reading (and compressing) the file shouldn't hold up the request
+        task::spawn_blocking(move || {
+            if let Err(e) = CACHE.promote(&key, max_memory) {
+                push_message(
+                    MsgType::Error,
+                    &format!("Failed to cache the route {:?}: {:#}", key, e),
+                );
+            }
+
+            CACHE.promoting.remove(&key);
+        });
+    }
+
+    /// Forget a route which has been created again (e.g. by the hot reloader).
+    pub fn forget(&self, key: &str) {
+        let mut resident = self.resident.lock();
+
+        if let Some(size) = resident.routes.remove(key) {
+            resident.used_memory -= size;
+        }
+
+        if let Some(size) = resident.pinned.remove(key) {
+            resident.used_memory -= size;
+        }
+    }
+
+    /// Forget a route which has been removed, along with its request count.
+    pub fn remove(&self, key: &str) {
+        self.forget(key);
+        self.requests.remove(key);
+    }
+
+    /// Account for a route which is always in memory, like the rendered templates, they
+    /// can't be evicted so they leave less memory to the routes cached on demand.
+    pub fn pin(&self, key: &str, size: u64) {
+        if self.max_memory.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+
+        let mut resident = self.resident.lock();
+
+        if let Some(previous_size) = resident.pinned.insert(key.into(), size) {
+            resident.used_memory -= previous_size;
+        }
+
+        resident.used_memory += size;
+    }
+
+    pub fn stats(&self) -> Stats {
+        let resident = self.resident.lock();
+        let max_memory = self.max_memory.load(Ordering::Relaxed);
+
+        Stats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            promotions: self.promotions.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            cached_routes: resident.routes.len() + resident.pinned.len(),
+            used_memory: resident.used_memory,
+            max_memory: (max_memory != 0).then_some(max_memory),
+        }
+    }
+
+    /// Count a request to a route, returns the number of requests to it.
+    fn count(&self, key: &str) -> u64 {
+        let total = self.hits.load(Ordering::Relaxed) + self.misses.load(Ordering::Relaxed);
+
+        if total.is_multiple_of(AGING_PERIOD) {
+            self.requests.retain(|_, count| {
+                *count /= 2;
+                *count > 0
+            });
+        }
+
+        let mut count = self.requests.entry(key.into()).or_default();
+        *count += 1;
+        *count
+    }
+
+    /// Load a route into memory, evicting the routes requested less often than it to make room.
+    fn promote(&self, key: &CompactString, max_memory: u64) -> anyhow::Result<()> {
+        let path = match ROUTEMAP.get(key) {
+            Some(handler)
+                if handler.r#type == Type::File && handler.response.built_bytes().is_none() =>
+            {
+                handler.response.path.to_owned()
+            }
+            _ => return Ok(()),
+        };
+
+        let static_file = StaticFile::create_in_memory(&path)?;
+        let size = static_file.memory_size();
+
+        // too large to be cached
+        if static_file.bytes.is_empty() || size > max_memory {
+            return Ok(());
+        }
+
+        let requests = self.requests.get(key).map_or(0, |count| *count);
+
+        let mut resident = self.resident.lock();
+
+        // the least requested routes make room for it, as long as they're requested less
+        let mut victims = Vec::new();
+        let mut available = max_memory.saturating_sub(resident.used_memory);
+
+        if available < size {
+            let mut candidates = resident
+                .routes
+                .iter()
+                .map(|(victim, &victim_size)| {
+                    let count = self.requests.get(victim).map_or(0, |count| *count);
+                    (count, victim.to_owned(), victim_size)
+                })
+                .filter(|(count, _, _)| *count < requests)
+                .collect::<Vec<_>>();
+
+            candidates.sort_unstable_by_key(|(count, _, _)| *count);
+
+            for (_, victim, victim_size) in candidates {
+                if available >= size {
+                    break;
+                }
+
+                available += victim_size;
+                victims.push(victim);
+            }
+
+            if available < size {
+                return Ok(());
+            }
+        }
+
+        let Some(mut handler) = ROUTEMAP.get_mut(key) else {
+            return Ok(());
+        };
+
+        // the file might have changed and been reloaded meanwhile
+        if handler.r#type != Type::File
+            || handler.response.last_modified != static_file.last_modified
+        {
+            return Ok(());
+        }
+
+        handler.r#type = Type::Bytes;
+        handler.response = static_file;
+        drop(handler);
+
+        for victim in victims {
+            if let Some(victim_size) = resident.routes.remove(&victim) {
+                resident.used_memory -= victim_size;
+            }
+
+            if let Some(mut handler) = ROUTEMAP.get_mut(&victim) {
+                evict(&mut handler);
+            }
+
+            self.evictions.fetch_add(1, Ordering::Relaxed);
+        }
+
+        resident.routes.insert(key.to_owned(), size);
+        resident.used_memory += size;
+
+        self.promotions.fetch_add(1, Ordering::Relaxed);
+
+        Ok(())
+    }
+}
+
+/// Drop the contents of a route from memory, it's served from disk again.
+fn evict(handler: &mut RouteHandle) {
+    handler.r#type = Type::File;
+    handler.response.bytes = Bytes::new();
+
+    // the variants compressed in-memory are gone, the sidecar files are read from disk
+    handler
+        .response
+        .variants
+        .retain(|variant| !variant.path.as_os_str().is_empty());
+
+    for variant in handler.response.variants.iter_mut() {
+        variant.bytes = Bytes::new();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use actix_web::http::header::HeaderValue;
+
+    use handlebars::{Context, Handlebars};
+
+    use crate::core::test_utils::{write_temp, TempFile};
+
+    /// A route served from disk, as created with a memory budget.
+    fn disk_route(name: &str) -> (CompactString, TempFile) {
+        let file = write_temp(&format!("cache-{}.txt", name), [b'x'; 100]);
+
+        let handlebars_handle = (Handlebars::new(), Context::wraps(()).unwrap());
+
+        let handler = RouteHandle {
+            r#type: Type::File,
+            response: StaticFile::create_on_disk(&file.0, &handlebars_handle).unwrap(),
+        };
+
+        let key = CompactString::from(format!("cache.test/{}", name));
+        ROUTEMAP.insert(key.to_owned(), handler);
+
+        (key, file)
+    }
+
+    fn requested(cache: &MemoryCache, key: &str, times: u64) {
+        for _ in 0..times {
+            cache.hit(key);
+        }
+    }
+
+    fn etag(key: &str) -> HeaderValue {
+        ROUTEMAP.get(key).unwrap().response.etag.to_owned().unwrap()
+    }
+
+    fn is_in_memory(key: &str) -> bool {
+        let handler = ROUTEMAP.get(key).unwrap();
+        handler.r#type == Type::Bytes && !handler.response.bytes.is_empty()
+    }
+
+    #[test]
+    fn promotes_the_routes_and_evicts_the_least_requested_ones() {
+        let cache = MemoryCache::default();
+        cache.reset(Some(250));
+
+        let (a, _a) = disk_route("a");
+        let (b, _b) = disk_route("b");
+        let (c, _c) = disk_route("c");
+
+        requested(&cache, &a, 3);
+        requested(&cache, &b, 1);
+
+        let disk_etag = etag(&b);
+
+        cache.promote(&a, 250).unwrap();
+        cache.promote(&b, 250).unwrap();
+        assert!(is_in_memory(&a) && is_in_memory(&b));
+        assert_eq!(etag(&b), disk_etag);
+        assert_eq!(cache.stats().used_memory, 200);
+
+        // it isn't requested more than any of the cached routes
+        cache.promote(&c, 250).unwrap();
+        assert!(!is_in_memory(&c));
+
+        // it is now, so it takes the place of the least requested one
+        requested(&cache, &c, 2);
+        cache.promote(&c, 250).unwrap();
+
+        assert!(is_in_memory(&a) && is_in_memory(&c));
+        assert!(!is_in_memory(&b));
+        assert!(ROUTEMAP.get(&b).unwrap().response.bytes.is_empty());
+        assert_eq!(etag(&b), disk_etag);
+
+        let stats = cache.stats();
+        assert_eq!((stats.promotions, stats.evictions), (3, 1));
+        assert_eq!((stats.cached_routes, stats.used_memory), (2, 200));
+
+        for key in [a, b, c] {
+            ROUTEMAP.remove(&key);
+        }
+    }
+
+    #[test]
+    fn accounts_for_the_pinned_and_forgotten_routes() {
+        let cache = MemoryCache::default();
+
+        // there's nothing to account for without a budget
+        cache.pin("pinned.test/template", 40);
+        assert_eq!(cache.stats().used_memory, 0);
+
+        cache.reset(Some(1000));
+
+        cache.pin("pinned.test/template", 40);
+        cache.pin("pinned.test/other", 10);
+
+        // rendered again
+        cache.pin("pinned.test/template", 60);
+        assert_eq!(cache.stats().used_memory, 70);
+
+        cache.forget("pinned.test/other");
+        assert_eq!(cache.stats().used_memory, 60);
+
+        requested(&cache, "pinned.test/template", 2);
+        cache.remove("pinned.test/template");
+
+        let stats = cache.stats();
+        assert_eq!((stats.cached_routes, stats.used_memory), (0, 0));
+        assert!(!cache.requests.contains_key("pinned.test/template"));
+    }
+}
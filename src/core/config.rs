@@ -4,10 +4,133 @@ use std::io::BufReader;
 use std::io::{self, prelude::*};
 use std::path::{Path, PathBuf};
 
-use serde::{Deserialize, Serialize};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize, Serializer};
 
 pub const CONFIG_FILE: &str = "binserve.json";
 
+/// Let's Encrypt production directory
+pub const LETSENCRYPT_DIRECTORY: &str = "https://acme-v02.api.letsencrypt.org/directory";
+
+fn letsencrypt_directory() -> String {
+    LETSENCRYPT_DIRECTORY.into()
+}
+
+fn acme_state_directory() -> PathBuf {
+    PathBuf::from(".binserve/acme")
+}
+
+const fn acme_renew_before_days() -> u64 {
+    30
+}
+
+/// Automatic HTTPS (ACME) configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Acme {
+    #[serde(default = "disabled")]
+    pub enable: bool,
+
+    #[serde(default)]
+    pub domains: Vec<String>,
+
+    #[serde(default)]
+    pub contact: Vec<String>,
+
+    #[serde(default = "letsencrypt_directory")]
+    pub directory: String,
+
+    #[serde(default)]
+    pub ca_root: PathBuf,
+
+    #[serde(default = "acme_state_directory")]
+    pub state_directory: PathBuf,
+
+    #[serde(default = "acme_renew_before_days")]
+    pub renew_before_days: u64,
+}
+
+impl Default for Acme {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            domains: Vec::new(),
+            contact: Vec::new(),
+            directory: letsencrypt_directory(),
+            ca_root: PathBuf::new(),
+            state_directory: acme_state_directory(),
+            renew_before_days: acme_renew_before_days(),
+        }
+    }
+}
+
+const fn ocsp_refresh_interval() -> u64 {
+    3600
+}
+
+/// OCSP stapling configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Ocsp {
+    #[serde(default = "disabled")]
+    pub enable: bool,
+
+    #[serde(default = "ocsp_refresh_interval")]
+    pub refresh_interval: u64,
+}
+
+impl Default for Ocsp {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            refresh_interval: ocsp_refresh_interval(),
+        }
+    }
+}
+
+/// Whether a client certificate is needed to connect
+#[derive(Default, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClientAuthMode {
+    #[default]
+    Required,
+    Optional,
+}
+
+/// Mutual TLS (client certificate authentication) configuration
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct ClientAuth {
+    #[serde(default = "disabled")]
+    pub enable: bool,
+
+    #[serde(default)]
+    pub ca: PathBuf,
+
+    #[serde(default)]
+    pub mode: ClientAuthMode,
+
+    #[serde(default)]
+    pub routes: Vec<String>,
+}
+
+/// TLS protocol versions supported by rustls
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum TlsVersion {
+    #[serde(rename = "1.2")]
+    Tls12,
+    #[serde(rename = "1.3")]
+    Tls13,
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct Certificate {
+    pub key: PathBuf,
+
+    pub cert: PathBuf,
+
+    #[serde(default)]
+    pub ocsp_response: PathBuf,
+}
+
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct Tls {
     pub host: String,
@@ -19,6 +142,37 @@ pub struct Tls {
 
     #[serde(default)]
     pub cert: PathBuf,
+
+    #[serde(default)]
+    pub ocsp_response: PathBuf,
+
+    #[serde(default)]
+    pub certificates: HashMap<String, Certificate>,
+
+    #[serde(default = "disabled")]
+    pub self_signed: bool,
+
+    #[serde(default)]
+    pub acme: Acme,
+
+    #[serde(default)]
+    pub ocsp: Ocsp,
+
+    #[serde(default)]
+    pub client_auth: ClientAuth,
+
+    #[serde(default)]
+    pub min_version: Option<TlsVersion>,
+
+    #[serde(default)]
+    pub max_version: Option<TlsVersion>,
+
+    #[serde(default)]
+    pub cipher_suites: Vec<String>,
+
+    // rejected at startup, the HTTP server offers `h2` and `http/1.1` only
+    #[serde(default)]
+    pub alpn: Vec<String>,
 }
 
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
@@ -39,6 +193,9 @@ pub struct Static {
 
     #[serde(default)]
     pub error_pages: HashMap<i16, PathBuf>,
+
+    #[serde(default = "disabled")]
+    pub fingerprint: bool,
 }
 
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
@@ -50,6 +207,14 @@ pub struct Template {
     pub variables: HashMap<String, String>,
 }
 
+const fn compress_min_size() -> u64 {
+    1024
+}
+
+const fn compress_max_size() -> u64 {
+    1_048_576
+}
+
 // configuration toggles
 const fn enabled() -> bool {
     true
@@ -66,6 +231,21 @@ pub struct Config {
     #[serde(default = "enabled")]
     pub fast_mem_cache: bool,
 
+    #[serde(default = "disabled")]
+    pub compress_mem_cache: bool,
+
+    #[serde(default = "compress_min_size")]
+    pub compress_min_size: u64,
+
+    #[serde(
+        default = "compress_max_size",
+        deserialize_with = "deserialize_compress_max_size"
+    )]
+    pub compress_max_size: u64,
+
+    #[serde(default = "enabled")]
+    pub content_hash_etags: bool,
+
     #[serde(default = "enabled")]
     pub enable_cache_control: bool,
 
@@ -75,6 +255,18 @@ pub struct Config {
     #[serde(default = "disabled")]
     pub minify_html: bool,
 
+    #[serde(default = "disabled")]
+    pub minify_css: bool,
+
+    #[serde(default = "disabled")]
+    pub minify_js: bool,
+
+    #[serde(default = "disabled")]
+    pub clean_urls: bool,
+
+    #[serde(default = "disabled")]
+    pub clean_urls_redirect: bool,
+
     #[serde(default = "disabled")]
     pub follow_symlinks: bool,
 
@@ -88,20 +280,233 @@ impl Default for Config {
         Self {
             enable_hot_reload: true,
             fast_mem_cache: true,
+            compress_mem_cache: false,
+            compress_min_size: compress_min_size(),
+            compress_max_size: compress_max_size(),
+            content_hash_etags: true,
             enable_cache_control: true,
             enable_directory_listing: false,
             minify_html: false,
+            minify_css: false,
+            minify_js: false,
+            clean_urls: false,
+            clean_urls_redirect: false,
             follow_symlinks: false,
             enable_logging: false,
         }
     }
 }
 
+const fn cache_max_file_size() -> u64 {
+    104_857_600
+}
+
+/// In-memory cache configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Cache {
+    #[serde(default, deserialize_with = "deserialize_size")]
+    pub max_memory: Option<u64>,
+
+    #[serde(
+        default = "cache_max_file_size",
+        deserialize_with = "deserialize_max_file_size"
+    )]
+    pub max_file_size: u64,
+
+    #[serde(default)]
+    pub include: Globs,
+
+    #[serde(default)]
+    pub exclude: Globs,
+
+    #[serde(default)]
+    pub stats_route: String,
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Self {
+            max_memory: None,
+            max_file_size: cache_max_file_size(),
+            include: Globs::default(),
+            exclude: Globs::default(),
+            stats_route: String::new(),
+        }
+    }
+}
+
+impl Cache {
+    /// Whether a file is allowed to be cached in memory, by its size and the `include`/`exclude` globs.
+    pub fn allows(&self, path: &Path, file_size: u64) -> bool {
+        file_size <= self.max_file_size
+            && (self.include.is_empty() || self.include.is_match(path))
+            && !self.exclude.is_match(path)
+    }
+}
+
+/// Glob patterns matched against the file paths, compiled once when the configuration is read
+#[derive(Default, Debug, Clone)]
+pub struct Globs {
+    patterns: Vec<String>,
+    set: GlobSet,
+}
+
+impl Globs {
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// Match a path like the ones in the configuration, `*` doesn't match `/` but `**` does.
+    pub fn is_match(&self, path: impl AsRef<Path>) -> bool {
+        let path = path.as_ref();
+        self.set.is_match(path.strip_prefix("./").unwrap_or(path))
+    }
+}
+
+impl Serialize for Globs {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.patterns.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Globs {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // a single pattern or a list of them
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Patterns {
+            One(String),
+            Many(Vec<String>),
+        }
+
+        let patterns = match Patterns::deserialize(deserializer)? {
+            Patterns::One(pattern) => vec![pattern],
+            Patterns::Many(patterns) => patterns,
+        };
+
+        let mut builder = GlobSetBuilder::new();
+
+        for pattern in &patterns {
+            let glob = GlobBuilder::new(pattern)
+                .literal_separator(true)
+                .build()
+                .map_err(D::Error::custom)?;
+
+            builder.add(glob);
+        }
+
+        let set = builder.build().map_err(D::Error::custom)?;
+
+        Ok(Self { patterns, set })
+    }
+}
+
+/// Parse a size like `512MB`, the units are multiples of 1024.
+pub fn parse_size(size: &str) -> Option<u64> {
+    let size = size.trim();
+    let (number, unit) = size.split_at(
+        size.find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(size.len()),
+    );
+
+    let multiplier = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        _ => return None,
+    };
+
+    number.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+/// Format a size in bytes with the largest unit fitting it, like `parse_size` reads them.
+pub fn format_size(bytes: u64) -> String {
+    match bytes {
+        0..=1023 => format!("{} B", bytes),
+        1024..=1_048_575 => format!("{:.1} KB", bytes as f64 / 1024.0),
+        1_048_576..=1_073_741_823 => format!("{:.1} MB", bytes as f64 / 1_048_576.0),
+        _ => format!("{:.1} GB", bytes as f64 / 1_073_741_824.0),
+    }
+}
+
+/// Sizes are either a number of bytes or a string like `"512MB"`.
+fn deserialize_size<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Size {
+        Bytes(u64),
+        Text(String),
+    }
+
+    match Option::<Size>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(Size::Bytes(bytes)) => Ok(Some(bytes)),
+        Some(Size::Text(text)) => parse_size(&text).map(Some).ok_or_else(|| {
+            D::Error::custom(format!(
+                "invalid size {:?}, expected a size like \"512MB\"",
+                text
+            ))
+        }),
+    }
+}
+
+/// A `Cache-Control` policy for the responses matching the request path and/or the MIME type globs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheControlRule {
+    #[serde(default)]
+    pub path: Globs,
+
+    #[serde(default)]
+    pub mime: Globs,
+
+    pub value: String,
+}
+
+impl CacheControlRule {
+    /// Whether the rule applies to a response, a rule without globs applies to all of them.
+    pub fn matches(&self, path: &str, mime: Option<&str>) -> bool {
+        (self.path.is_empty() || self.path.is_match(path))
+            && (self.mime.is_empty() || mime.is_some_and(|mime| self.mime.is_match(mime)))
+    }
+}
+
+fn deserialize_max_file_size<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    Ok(deserialize_size(deserializer)?.unwrap_or_else(cache_max_file_size))
+}
+
+fn deserialize_compress_max_size<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<u64, D::Error> {
+    Ok(deserialize_size(deserializer)?.unwrap_or_else(compress_max_size))
+}
+
+/// A virtual host with its own routing table
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct Site {
+    pub routes: HashMap<String, PathBuf>,
+
+    #[serde(default)]
+    pub r#static: Static,
+
+    #[serde(default)]
+    pub template: Template,
+
+    #[serde(default)]
+    pub insert_headers: HashMap<String, String>,
+
+    #[serde(default)]
+    pub spa_fallback: HashMap<String, String>,
+}
+
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct BinserveConfig {
     pub server: Server,
     pub routes: HashMap<String, PathBuf>,
 
+    #[serde(default)]
+    pub spa_fallback: HashMap<String, String>,
+
     #[serde(default)]
     pub r#static: Static,
 
@@ -111,23 +516,68 @@ pub struct BinserveConfig {
     #[serde(default)]
     pub config: Config,
 
+    #[serde(default)]
+    pub cache: Cache,
+
+    #[serde(default)]
+    pub cache_control: Vec<CacheControlRule>,
+
     #[serde(default)]
     pub insert_headers: HashMap<String, String>,
+
+    #[serde(default)]
+    pub sites: HashMap<String, Site>,
 }
 
-use once_cell::sync::Lazy;
+use once_cell::sync::{Lazy, OnceCell};
 use parking_lot::Mutex;
 
 /// A universal config state
 pub static CONFIG_STATE: Lazy<Mutex<BinserveConfig>> =
     Lazy::new(|| Mutex::new(BinserveConfig::default()));
 
+/// Command-line overrides of the configuration file
+#[derive(Default, Debug, Clone)]
+pub struct Overrides {
+    pub host: Option<String>,
+    pub tls_key: Option<PathBuf>,
+    pub tls_cert: Option<PathBuf>,
+}
+
+/// The overrides are applied whenever the configuration file is read,
+/// so the hot reloads keep serving the files the server started with.
+static OVERRIDES: OnceCell<Overrides> = OnceCell::new();
+
 impl BinserveConfig {
-    /// Read and serialize the config file.
+    /// The default site, served for hosts not matching any of the `sites`.
+    pub fn default_site(&self) -> Site {
+        Site {
+            routes: self.routes.to_owned(),
+            r#static: self.r#static.to_owned(),
+            template: self.template.to_owned(),
+            insert_headers: self.insert_headers.to_owned(),
+            spa_fallback: self.spa_fallback.to_owned(),
+        }
+    }
+
+    /// Get a site by name, the default site is named `""`.
+    pub fn site(&self, name: &str) -> Option<Site> {
+        if name.is_empty() {
+            return Some(self.default_site());
+        }
+
+        self.sites.get(name).cloned()
+    }
+
+    /// Override the configuration file with the command-line arguments from now on.
+    pub fn set_overrides(overrides: Overrides) {
+        let _ = OVERRIDES.set(overrides);
+    }
+
+    /// Read and serialize the config file, along with the command-line overrides.
     pub fn read() -> io::Result<Self> {
         let config_file = File::open(CONFIG_FILE)?;
-        let buf_reader = BufReader::new(config_file);
-        let config: BinserveConfig = serde_json::from_reader(buf_reader)?;
+        let config = Self::parse(BufReader::new(config_file))?;
 
         // update global config state
         *CONFIG_STATE.lock() = config.to_owned();
@@ -135,6 +585,55 @@ impl BinserveConfig {
         Ok(config)
     }
 
+    /// Read the config file, or the default configuration if there's none (without generating it).
+    pub fn read_or_default() -> io::Result<Self> {
+        if Path::new(CONFIG_FILE).exists() {
+            return Self::read();
+        }
+
+        Self::parse(&include_bytes!("config.json")[..])
+    }
+
+    /// Serialize a configuration, along with the command-line overrides.
+    fn parse(reader: impl Read) -> io::Result<Self> {
+        let mut config: BinserveConfig = serde_json::from_reader(reader)?;
+
+        // host names are case-insensitive, the sites are named in lowercase everywhere
+        let mut sites = HashMap::with_capacity(config.sites.len());
+
+        for (name, site) in config.sites {
+            let name = name.to_ascii_lowercase();
+
+            if sites.contains_key(&name) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "The site {:?} is configured more than once in `sites`",
+                        name
+                    ),
+                ));
+            }
+
+            sites.insert(name, site);
+        }
+
+        config.sites = sites;
+
+        if let Some(overrides) = OVERRIDES.get() {
+            if let Some(host) = &overrides.host {
+                config.server.host = host.to_owned();
+            }
+            if let Some(tls_key) = &overrides.tls_key {
+                config.server.tls.key = tls_key.to_owned();
+            }
+            if let Some(tls_cert) = &overrides.tls_cert {
+                config.server.tls.cert = tls_cert.to_owned();
+            }
+        }
+
+        Ok(config)
+    }
+
     /// Generate a boilerplate binserve configuration file.
     pub fn generate_default_config() -> io::Result<()> {
         if !Path::new(CONFIG_FILE).exists() {
@@ -0,0 +1,177 @@
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, DistinguishedName, DnType,
+    ExtendedKeyUsagePurpose, IsCa, KeyPair, KeyUsagePurpose,
+};
+
+use time::{Duration, OffsetDateTime};
+
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+
+use super::config::Server;
+use super::files::write_private;
+
+use crate::cli::messages::{push_message, Type};
+
+/// Directory of the local development CA
+const CA_DIRECTORY: &str = ".binserve/dev-ca";
+const CA_CERT_FILE: &str = "ca.pem";
+const CA_KEY_FILE: &str = "ca.key";
+
+/// Validity of the local CA
+const CA_VALIDITY_DAYS: i64 = 3650;
+
+/// Validity of the development certificates, some clients (like Apple platforms)
+/// reject TLS server certificates valid for more than 825 days.
+const VALIDITY_DAYS: i64 = 825;
+
+/// Create the development certificate unless it already exists. (`tls.self_signed`)
+pub fn ensure_certificate(server: &Server) -> Result<()> {
+    if server.tls.cert.exists() && server.tls.key.exists() {
+        return Ok(());
+    }
+
+    create_certificate(server)
+}
+
+/// Create a certificate for local development, signed by the local CA.
+///
+/// It's written to the `key`/`cert` paths of the TLS configuration.
+pub fn create_certificate(server: &Server) -> Result<()> {
+    let tls = &server.tls;
+
+    if tls.cert.as_os_str().is_empty() || tls.key.as_os_str().is_empty() {
+        bail!("Set `server.tls.key` and `server.tls.cert` to write the development certificate to");
+    }
+
+    let (ca_cert, ca_key) = load_or_create_ca()?;
+
+    let host_names = host_names(server);
+
+    let mut params = CertificateParams::new(host_names.to_owned())?;
+    params.distinguished_name = DistinguishedName::new();
+    params
+        .distinguished_name
+        .push(DnType::CommonName, "binserve development certificate");
+    params.key_usages = vec![KeyUsagePurpose::DigitalSignature];
+    params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+    params.use_authority_key_identifier_extension = true;
+    set_validity(&mut params, VALIDITY_DAYS);
+
+    let key_pair = KeyPair::generate()?;
+    let cert = params.signed_by(&key_pair, &ca_cert, &ca_key)?;
+
+    for path in [&tls.cert, &tls.key] {
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+    }
+
+    fs::write(&tls.cert, cert.pem())
+        .with_context(|| format!("Failed to write file {:?}", tls.cert.to_string_lossy()))?;
+    write_private(&tls.key, &key_pair.serialize_pem())
+        .with_context(|| format!("Failed to write file {:?}", tls.key.to_string_lossy()))?;
+
+    push_message(
+        Type::Success,
+        &format!(
+            "Created a development certificate for {} 🔒",
+            host_names.join(", ")
+        ),
+    );
+
+    Ok(())
+}
+
+/// Load the local CA or create one on the first run.
+fn load_or_create_ca() -> Result<(Certificate, KeyPair)> {
+    let directory = Path::new(CA_DIRECTORY);
+    let cert_path = directory.join(CA_CERT_FILE);
+    let key_path = directory.join(CA_KEY_FILE);
+
+    if let (Ok(cert_pem), Ok(key_pem)) = (
+        fs::read_to_string(&cert_path),
+        fs::read_to_string(&key_path),
+    ) {
+        let key_pair = KeyPair::from_pem(&key_pem).with_context(|| {
+            format!("Failed to load the CA key {:?}", key_path.to_string_lossy())
+        })?;
+
+        // re-signing keeps the subject and key, so the already trusted CA still applies
+        let cert = CertificateParams::from_ca_cert_pem(&cert_pem)
+            .with_context(|| format!("Failed to load the CA {:?}", cert_path.to_string_lossy()))?
+            .self_signed(&key_pair)?;
+
+        return Ok((cert, key_pair));
+    }
+
+    let mut params = CertificateParams::default();
+    params.distinguished_name = DistinguishedName::new();
+    params
+        .distinguished_name
+        .push(DnType::CommonName, "binserve development CA");
+    params.is_ca = IsCa::Ca(BasicConstraints::Constrained(0));
+    params.key_usages = vec![
+        KeyUsagePurpose::KeyCertSign,
+        KeyUsagePurpose::CrlSign,
+        KeyUsagePurpose::DigitalSignature,
+    ];
+    set_validity(&mut params, CA_VALIDITY_DAYS);
+
+    let key_pair = KeyPair::generate()?;
+    let cert = params.self_signed(&key_pair)?;
+
+    fs::create_dir_all(directory)?;
+    fs::write(&cert_path, cert.pem())?;
+    write_private(&key_path, &key_pair.serialize_pem())?;
+
+    push_message(
+        Type::Info,
+        &format!(
+            "Created a local CA, add {:?} to your trust store to avoid browser warnings",
+            cert_path.to_string_lossy()
+        ),
+    );
+
+    Ok((cert, key_pair))
+}
+
+/// Valid from a day ago (for clock skew) for the given number of days.
+fn set_validity(params: &mut CertificateParams, days: i64) {
+    let now = OffsetDateTime::now_utc();
+
+    params.not_before = now - Duration::days(1);
+    params.not_after = now + Duration::days(days);
+}
+
+/// `localhost` and the loopback addresses, along with the configured hosts.
+fn host_names(server: &Server) -> Vec<String> {
+    let mut host_names: Vec<String> = vec!["localhost".into(), "127.0.0.1".into(), "::1".into()];
+
+    for host in [&server.host, &server.tls.host] {
+        // strip the port
+        let host = match (host.parse::<SocketAddr>(), host.parse::<IpAddr>()) {
+            (Ok(address), _) => address.ip().to_string(),
+            (_, Ok(ip)) => ip.to_string(),
+            _ => host
+                .split(':')
+                .next()
+                .unwrap_or_default()
+                .to_ascii_lowercase(),
+        };
+
+        // skip the wildcard addresses like `0.0.0.0`
+        if host.is_empty() || host.parse::<IpAddr>().is_ok_and(|ip| ip.is_unspecified()) {
+            continue;
+        }
+
+        if !host_names.contains(&host) {
+            host_names.push(host);
+        }
+    }
+
+    host_names
+}
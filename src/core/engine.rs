@@ -1,12 +1,40 @@
 use crate::cli::interface;
 
-use super::{config::BinserveConfig, files, routes::RouteHandle, server, templates, watcher};
+use actix_web::http::header::HeaderValue;
+
+use std::path::PathBuf;
+
+use super::{
+    config::{format_size, BinserveConfig, Overrides},
+    dev_certs, files,
+    routes::{RouteHandle, ROUTEMAP},
+    server, watcher,
+};
 
 use crate::cli::messages::{push_message, Type};
 
 pub fn init() -> anyhow::Result<()> {
     let start_time = std::time::Instant::now();
 
+    // override with cli configurations if any, whenever the configuration file is read
+    let cli_args = interface::args();
+    BinserveConfig::set_overrides(Overrides {
+        host: cli_args.get_one::<String>("host").cloned(),
+        tls_key: cli_args.get_one::<String>("tls_key").map(PathBuf::from),
+        tls_cert: cli_args.get_one::<String>("tls_cert").map(PathBuf::from),
+    });
+
+    // `binserve cert dev` creates a certificate for local development,
+    // it doesn't generate the boilerplate of a server it won't start
+    if let Some(("cert", cert_args)) = cli_args.subcommand() {
+        if let Some(("dev", _)) = cert_args.subcommand() {
+            let config = BinserveConfig::read_or_default()?;
+            dev_certs::create_certificate(&config.server)?;
+        }
+
+        return Ok(());
+    }
+
     // generate the boilerplate starter public directory
     files::generate_starter_boilerplate()?;
 
@@ -14,25 +42,22 @@ pub fn init() -> anyhow::Result<()> {
     BinserveConfig::generate_default_config()?;
 
     // read the configuration file
-    let mut config = BinserveConfig::read()?;
+    let config = BinserveConfig::read()?;
 
-    // override with cli configurations if any
-    let cli_args = interface::args();
-    if let Some(host) = cli_args.get_one::<String>("host") {
-        config.server.host = host.into();
-    }
-    if let Some(tls_key) = cli_args.get_one::<String>("tls_key") {
-        config.server.tls.key = tls_key.into();
-    }
-    if let Some(tls_cert) = cli_args.get_one::<String>("tls_cert") {
-        config.server.tls.key = tls_cert.into();
+    // create the development certificate on the first run
+    if config.server.tls.enable && config.server.tls.self_signed {
+        dev_certs::ensure_certificate(&config.server)?;
     }
 
-    // prepare template partials
-    let handlebars_handle = templates::render_templates(&config)?;
+    // the `Cache-Control` policies have to be valid header values
+    for rule in &config.cache_control {
+        if HeaderValue::from_str(&rule.value).is_err() {
+            anyhow::bail!("Invalid `cache_control` value {:?}", rule.value);
+        }
+    }
 
-    // prepare routes table
-    RouteHandle::add_routes(&config.routes, &handlebars_handle)?;
+    // prepare template partials and routes tables
+    RouteHandle::add_sites(&config)?;
 
     let end_time = start_time.elapsed();
 
@@ -52,12 +77,58 @@ pub fn init() -> anyhow::Result<()> {
         push_message(Type::Info, "Enabled TLS (HTTPS) 🔒")
     }
 
+    if !config.sites.is_empty() {
+        push_message(
+            Type::Info,
+            &format!("Serving {} virtual host(s) 🌐", config.sites.len()),
+        )
+    }
+
+    // memory taken by the variants compressed at build time
+    let (compressed_routes, compressed_size) = ROUTEMAP
+        .iter()
+        .map(|route| {
+            route
+                .response
+                .variants
+                .iter()
+                .filter(|variant| variant.path.as_os_str().is_empty())
+                .map(|variant| variant.bytes.len())
+                .sum::<usize>()
+        })
+        .filter(|&size| size > 0)
+        .fold((0, 0), |(count, total), size| (count + 1, total + size));
+
+    if compressed_routes > 0 {
+        push_message(
+            Type::Info,
+            &format!(
+                "Compressed {} cached route(s), using {} more memory 🗜️",
+                compressed_routes,
+                format_size(compressed_size as u64)
+            ),
+        )
+    }
+
+    if let Some(max_memory) = config.cache.max_memory {
+        push_message(
+            Type::Info,
+            &format!(
+                "Caching the most requested routes within {} 🧠",
+                format_size(max_memory)
+            ),
+        )
+    }
+
     if config.config.enable_logging {
         push_message(Type::Info, "Enabled logging 📜")
     }
 
     // start the hot reloader (file wacther)
-    std::thread::spawn(watcher::hot_reload_files);
+    std::thread::spawn({
+        let config = config.to_owned();
+        move || watcher::hot_reload_files(config)
+    });
 
     // and finally server take off!
     server::run_server(config)?;
@@ -1,6 +1,10 @@
 use actix_web::{
-    http::header::{HeaderValue, HttpDate},
+    http::{
+        header::{AcceptEncoding, ContentEncoding, Encoding, Header, HeaderValue, HttpDate},
+        StatusCode,
+    },
     web::Bytes,
+    HttpMessage,
 };
 
 use std::collections::HashMap;
@@ -8,15 +12,26 @@ use std::fs::{self, File};
 use std::io::{self, prelude::*};
 use std::path::{Path, PathBuf};
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 
 use etag::EntityTag;
 
+use flate2::{write::GzEncoder, Compression};
+
 use handlebars::{Context as HbsContext, Handlebars};
 
+use lightningcss::stylesheet::{MinifyOptions, ParserOptions, PrinterOptions, StyleSheet};
+
 use minify_html_onepass::Cfg;
 
-use super::config::{CONFIG_FILE, CONFIG_STATE};
+use minify_js::{Session, TopLevelMode};
+
+use xxhash_rust::xxh3::{xxh3_128, Xxh3};
+
+use super::assets;
+use super::config::{Config, Static, CONFIG_FILE, CONFIG_STATE};
+
+use crate::cli::messages::{push_message, Type as MsgType};
 
 /// Represents a static file
 #[derive(Debug)]
@@ -27,30 +42,162 @@ pub struct StaticFile {
     pub etag: Option<HeaderValue>,          // etag header value (RFC 7232 §2.3)
     pub last_modified: Option<HeaderValue>, // last modified system time (RFC 7232 §2.2)
     pub hbs_bytes: Bytes,                   // to read pre-rendered handlebars content
+    pub minified_bytes: Bytes,              // minified stylesheet/script (`minify_css`/`minify_js`)
+    pub variants: Vec<Variant>,             // precompressed variants of the file
 }
 
-/// Max file size allowed to be cached in memory
-const MAX_FILE_SIZE: u64 = 104_857_600;
+/// A precompressed variant of a static file (e.g. `app.js.br` for `app.js`)
+#[derive(Debug)]
+pub struct Variant {
+    pub encoding: ContentEncoding, // `Content-Encoding` of the variant
+    pub bytes: Bytes,              // contents of the variant, empty if it's read from disk
+    pub path: PathBuf,             // path to the variant in disk, empty if compressed in-memory
+    pub etag: HeaderValue,         // etag of the variant, it's a different representation
+}
+
+/// Extensions of the precompressed sidecar files and their encodings
+const SIDECARS: [(&str, ContentEncoding); 3] = [
+    ("br", ContentEncoding::Brotli),
+    ("zst", ContentEncoding::Zstd),
+    ("gz", ContentEncoding::Gzip),
+];
+
+/// Compression levels of the in-memory variants, it's done at build time but also on
+/// every hot reload, so the levels trade a bit of ratio for a much faster build.
+const BROTLI_QUALITY: u32 = 5;
+const BROTLI_WINDOW: u32 = 22;
+const ZSTD_LEVEL: i32 = 3;
+const GZIP_LEVEL: u32 = 6;
+
+/// MIME types worth compressing, besides `text/*`
+const COMPRESSIBLE_TYPES: [&str; 8] = [
+    "application/javascript",
+    "application/json",
+    "application/manifest+json",
+    "application/wasm",
+    "application/xml",
+    "image/svg+xml",
+    "font/otf",
+    "font/ttf",
+];
+
+/// Where the contents of a static file are kept
+enum Storage {
+    /// read from disk on every request
+    Disk,
+    /// in memory, unless it's ruled out by `cache.max_file_size` or the `cache` globs
+    Cache,
+    /// always in memory
+    Memory,
+}
 
 impl StaticFile {
     /// Creates a static file instance
     pub fn create(path: &PathBuf, handlebars_handle: &(Handlebars, HbsContext)) -> Result<Self> {
-        // read the current config state
-        let config_state = &*CONFIG_STATE.lock();
+        // with a memory budget, only the most requested files are cached (see `cache.rs`)
+        let storage = {
+            let config_state = &*CONFIG_STATE.lock();
+
+            match config_state.config.fast_mem_cache && config_state.cache.max_memory.is_none() {
+                true => Storage::Cache,
+                false => Storage::Disk,
+            }
+        };
+
+        Self::load(path, Some(handlebars_handle), storage)
+    }
+
+    /// Creates a static file instance served from disk, like the static file service does.
+    ///
+    /// The minified files (and rendered templates) are in memory all the same.
+    pub fn create_on_disk(
+        path: &PathBuf,
+        handlebars_handle: &(Handlebars, HbsContext),
+    ) -> Result<Self> {
+        Self::load(path, Some(handlebars_handle), Storage::Disk)
+    }
+
+    /// Creates a static file instance with its contents in memory, unless it's too large.
+    ///
+    /// Handlebars templates are rendered along with their site, so they can't be loaded this way.
+    pub fn create_in_memory(path: &PathBuf) -> Result<Self> {
+        Self::load(path, None, Storage::Cache)
+    }
+
+    /// Creates an error page, it's always in memory.
+    ///
+    /// Handlebars error pages are rendered per request along with the status code and path
+    /// (see `templates::render_error_page`), so they're kept as they are.
+    pub fn create_error_page(path: &PathBuf) -> Result<Self> {
+        if !is_template(path) {
+            return Self::load(path, None, Storage::Memory);
+        }
 
+        let template = fs::read(path)
+            .with_context(|| format!("Failed to read file {:?}", path.to_string_lossy()))?;
+
+        Ok(Self {
+            mime: Some(HeaderValue::from_static("text/html")),
+            bytes: Bytes::from(template),
+            path: path.to_path_buf(),
+            etag: None,
+            last_modified: None,
+            hbs_bytes: Bytes::new(),
+            minified_bytes: Bytes::new(),
+            variants: Vec::new(),
+        })
+    }
+
+    fn load(
+        path: &PathBuf,
+        handlebars_handle: Option<&(Handlebars, HbsContext)>,
+        storage: Storage,
+    ) -> Result<Self> {
         // read the file
         let file = File::open(path)
             .with_context(|| format!("Failed to read file {:?}", path.to_string_lossy()))?;
 
         let file_size = file.metadata()?.len();
 
-        // file contents as Bytes object
-        let mut contents = Bytes::from(fs::read(path)?);
+        // identify handlebars template
+        let is_template = is_template(path);
+
+        // the config lock is only held to copy the settings, the static file service takes it
+        // on every request so it isn't held while the file is read (and compressed)
+        let (config, in_memory) = {
+            let config_state = CONFIG_STATE.lock();
+
+            // the file is cached if it's within `cache.max_file_size` and allowed by the `cache` globs
+            let in_memory = match storage {
+                Storage::Disk => false,
+                Storage::Cache => config_state.cache.allows(path, file_size),
+                Storage::Memory => true,
+            };
+
+            (config_state.config.to_owned(), in_memory)
+        };
+
+        // get the mime type of the file
+        // by default the mime type falls to `application/octet-stream`
+        let mut mime_type = new_mime_guess::from_path(path)
+            .first_raw()
+            .unwrap_or("application/octet-stream");
+
+        // minified stylesheets and scripts are kept in-memory like the rendered templates,
+        // so they're always served minified (and as hashed by the `sri` helper)
+        let minify = !is_template && should_minify(path, mime_type, &config);
+
+        // file contents as Bytes object, files served from disk aren't read at all
+        let mut contents = Bytes::new();
+
+        if is_template || in_memory || minify {
+            contents = Bytes::from(fs::read(path)?);
+        }
 
         let file_metadata = std::fs::metadata(path)?;
 
         // if configured not to follow symlinks
-        if file_metadata.is_symlink() && !config_state.config.follow_symlinks {
+        if file_metadata.is_symlink() && !config.follow_symlinks {
             return Ok(Self {
                 mime: None,
                 bytes: Bytes::new(),
@@ -58,59 +205,102 @@ impl StaticFile {
                 etag: None,
                 last_modified: None,
                 hbs_bytes: Bytes::new(),
+                minified_bytes: Bytes::new(),
+                variants: Vec::new(),
             });
         }
 
         // derive an etag from the file's metadata
-        let etag = EntityTag::from_file_meta(&file_metadata);
+        let mut etag = EntityTag::from_file_meta(&file_metadata);
 
         // derive the last modified time of the file
         let last_modified: HttpDate = file_metadata.modified()?.into();
 
-        // get the mime type of the file
-        // by default the mime type falls to `application/octet-stream`
-        let mut mime_type = new_mime_guess::from_path(path)
-            .first_raw()
-            .unwrap_or("application/octet-stream");
-
         // render handlebars templates (.hbs templates)
         let mut hbs_prerendered_bytes = Bytes::new();
 
-        let ext = path.extension();
-
-        if ext.is_some() {
-            let extension: &str = ext.unwrap().to_str().unwrap();
+        if is_template {
+            mime_type = "text/html"; // it's rendered to HTML at initialization
 
-            // identify handlebars template
-            if extension == "hbs" {
-                mime_type = "text/html"; // it's rendered to HTML at initialization
+            // handlebars registered handle and context with the template variables
+            let Some((hbs_reg, hbs_ctx)) = handlebars_handle else {
+                bail!(
+                    "Failed to render {:?}, templates are rendered along with their site",
+                    path.to_string_lossy()
+                );
+            };
 
-                // handlebars registered handle and context with the template variables
-                let (hbs_reg, hbs_ctx) = handlebars_handle;
-
-                // render the template
-                contents = Bytes::from(hbs_reg.render_template_with_context(
+            // render the template
+            contents =
+                Bytes::from(hbs_reg.render_template_with_context(
                     &String::from_utf8_lossy(&contents[..]),
                     hbs_ctx,
                 )?);
 
-                hbs_prerendered_bytes = contents.to_owned();
-            }
+            hbs_prerendered_bytes = contents.to_owned();
         }
 
         // minify html if configured
-        if mime_type == "text/html" && config_state.config.minify_html {
-            if let Ok(minified_html) = minify_html_onepass::copy(&contents, &Cfg::new()) {
-                contents = Bytes::from(minified_html);
+        if mime_type == "text/html" && config.minify_html {
+            contents = minify_html(contents);
+        }
+
+        // minify css and js if configured, a file which fails to minify is served unchanged
+        let mut minified_bytes = Bytes::new();
+
+        if minify {
+            match self::minify(path, mime_type, &contents) {
+                Ok(minified) => contents = Bytes::from(minified),
+                Err(e) => push_message(
+                    MsgType::Warning,
+                    &format!(
+                        "Failed to minify {:?}, serving it unminified: {:#}",
+                        path.to_string_lossy(),
+                        e
+                    ),
+                ),
+            }
+
+            minified_bytes = contents.to_owned();
+        }
+
+        // a strong etag from the final contents of the in-memory files, identical files
+        // get the same etag regardless of when (or on which server) they were deployed
+        let content_hash_etags = config.content_hash_etags;
+
+        if content_hash_etags && in_memory {
+            etag = content_etag(&contents);
+        } else if content_hash_etags && is_template {
+            etag = content_etag(&hbs_prerendered_bytes);
+        } else if content_hash_etags && minify {
+            etag = content_etag(&minified_bytes);
+        } else if content_hash_etags {
+            // the same etag as once it's cached (`cache.max_memory`), the file is hashed
+            // in chunks as it's served from disk
+            etag = file_content_etag(path)?;
+        }
+
+        // precompressed sidecar files, templates are rendered so they can't have any
+        // and the sidecars of the minified files aren't (the minified representation)
+        let mut variants = Vec::new();
+
+        if !is_template && !minify {
+            variants = find_variants(path, config.follow_symlinks)?;
+        }
+
+        if content_hash_etags && !in_memory {
+            for variant in variants.iter_mut() {
+                variant.etag =
+                    HeaderValue::from_str(&file_content_etag(&variant.path)?.to_string())?;
             }
         }
 
         // prepared header values
         let mime = Some(HeaderValue::from_str(mime_type)?);
-        let etag = Some(HeaderValue::from_str(&etag.to_string())?);
+        let etag_header = Some(HeaderValue::from_str(&etag.to_string())?);
         let last_modified = Some(HeaderValue::from_str(&last_modified.to_string())?);
 
-        // only save the file in-memory if the size is less than 100 MB
+        // only save the file in-memory if the size is within `cache.max_file_size` (100 MB by default)
         //
         // So what if multiple files cumulatively make up to a bigger size?
         // That's the reason `fast_mem_cache` feature exists. Disable it in
@@ -119,17 +309,59 @@ impl StaticFile {
         // one should totally disable in-memory caching of files unless you
         // have a chonker of a RAM.
         //
-        // TODO: How about caching frequently accessed files? Like a priority cache?
+        // With `cache.max_memory` set, the files are rather cached on demand,
+        // the most requested ones within the memory budget (see `cache.rs`).
         //
         // It skips this whole step if the `fast_mem_cache` feature is disabled.
-        if file_size < MAX_FILE_SIZE && config_state.config.fast_mem_cache {
+        if in_memory {
+            for variant in variants.iter_mut() {
+                variant.bytes = Bytes::from(fs::read(&variant.path)?);
+
+                if content_hash_etags {
+                    variant.etag =
+                        HeaderValue::from_str(&content_etag(&variant.bytes).to_string())?;
+                }
+            }
+
+            // compress the cached contents, the sidecar files take precedence
+            if config.compress_mem_cache
+                && contents.len() as u64 >= config.compress_min_size
+                && contents.len() as u64 <= config.compress_max_size
+                && is_compressible(mime_type)
+            {
+                for (extension, encoding) in SIDECARS {
+                    if variants.iter().any(|variant| variant.encoding == encoding) {
+                        continue;
+                    }
+
+                    let compressed = compress(&contents, encoding)?;
+
+                    // not worth it if it doesn't get any smaller
+                    if compressed.len() >= contents.len() {
+                        continue;
+                    }
+
+                    let variant_etag =
+                        EntityTag::new(etag.weak, &format!("{}-{}", etag.tag(), extension));
+
+                    variants.push(Variant {
+                        encoding,
+                        bytes: Bytes::from(compressed),
+                        path: PathBuf::new(),
+                        etag: HeaderValue::from_str(&variant_etag.to_string())?,
+                    });
+                }
+            }
+
             return Ok(Self {
                 mime,
                 bytes: contents,
                 path: path.to_path_buf(),
-                etag,
+                etag: etag_header,
                 last_modified,
                 hbs_bytes: Bytes::new(),
+                minified_bytes: Bytes::new(),
+                variants,
             });
         }
 
@@ -138,41 +370,272 @@ impl StaticFile {
             mime,
             bytes: Bytes::new(),
             path: path.to_path_buf(),
-            etag,
+            etag: etag_header,
             last_modified,
             hbs_bytes: hbs_prerendered_bytes,
+            minified_bytes,
+            variants,
         })
     }
+
+    /// The contents rendered (templates) or minified at build time, they're served
+    /// from memory even when the file itself isn't cached.
+    pub fn built_bytes(&self) -> Option<&Bytes> {
+        if !self.hbs_bytes.is_empty() || is_template(&self.path) {
+            Some(&self.hbs_bytes)
+        } else if !self.minified_bytes.is_empty() {
+            Some(&self.minified_bytes)
+        } else {
+            None
+        }
+    }
+
+    /// Bytes of memory taken by the contents of the file and its variants.
+    pub fn memory_size(&self) -> u64 {
+        let variants_size: usize = self
+            .variants
+            .iter()
+            .map(|variant| variant.bytes.len())
+            .sum();
+
+        (self.bytes.len() + self.hbs_bytes.len() + self.minified_bytes.len() + variants_size) as u64
+    }
+}
+
+/// Find the precompressed sidecar files of a file, `app.js.br`, `app.js.zst` and `app.js.gz` for `app.js`.
+///
+/// Sidecars older than the file itself are stale and skipped.
+pub fn find_variants(path: &Path, follow_symlinks: bool) -> Result<Vec<Variant>> {
+    let modified = fs::metadata(path)?.modified()?;
+
+    let mut variants = Vec::new();
+
+    for (extension, encoding) in SIDECARS {
+        let mut sidecar = path.as_os_str().to_owned();
+        sidecar.push(".");
+        sidecar.push(extension);
+        let sidecar = PathBuf::from(sidecar);
+
+        let Ok(metadata) = sidecar.symlink_metadata() else {
+            continue;
+        };
+
+        // if configured not to follow symlinks
+        if metadata.is_symlink() && !follow_symlinks {
+            continue;
+        }
+
+        let Ok(metadata) = fs::metadata(&sidecar) else {
+            continue;
+        };
+
+        if !metadata.is_file() || metadata.modified()? < modified {
+            continue;
+        }
+
+        let etag = EntityTag::from_file_meta(&metadata);
+
+        variants.push(Variant {
+            encoding,
+            bytes: Bytes::new(),
+            path: sidecar,
+            etag: HeaderValue::from_str(&etag.to_string())?,
+        });
+    }
+
+    Ok(variants)
+}
+
+/// The `ETag` and `Last-Modified` header values of a file, derived from its metadata.
+pub fn validators(metadata: &fs::Metadata) -> io::Result<(HeaderValue, HeaderValue)> {
+    let etag = EntityTag::from_file_meta(metadata);
+    let last_modified: HttpDate = metadata.modified()?.into();
+
+    Ok((
+        HeaderValue::from_str(&etag.to_string()).map_err(io::Error::other)?,
+        HeaderValue::from_str(&last_modified.to_string()).map_err(io::Error::other)?,
+    ))
 }
 
-/// Generate the 404 Not Found template.
-pub fn generate_not_found() -> Result<StaticFile> {
-    let config = &*CONFIG_STATE.lock();
+/// A strong etag from a hash of the contents.
+fn content_etag(contents: &[u8]) -> EntityTag {
+    EntityTag::strong(&format!("{:032x}", xxh3_128(contents)))
+}
+
+/// A strong etag from a hash of the contents of a file, the same as `content_etag` of them.
+fn file_content_etag(path: &Path) -> io::Result<EntityTag> {
+    let mut hasher = Xxh3::new();
+    assets::read_chunks(path, |chunk| hasher.update(chunk))?;
+
+    Ok(EntityTag::strong(&format!("{:032x}", hasher.digest128())))
+}
+
+/// Whether a MIME type is worth compressing, already compressed formats (images, fonts, archives) aren't.
+fn is_compressible(mime_type: &str) -> bool {
+    mime_type.starts_with("text/") || COMPRESSIBLE_TYPES.contains(&mime_type)
+}
+
+/// Compress the contents of a file with the given encoding.
+fn compress(contents: &[u8], encoding: ContentEncoding) -> Result<Vec<u8>> {
+    let compressed = match encoding {
+        ContentEncoding::Brotli => {
+            let mut encoder =
+                brotli::CompressorWriter::new(Vec::new(), 4096, BROTLI_QUALITY, BROTLI_WINDOW);
+            encoder.write_all(contents)?;
+            encoder.into_inner()
+        }
+        ContentEncoding::Zstd => zstd::bulk::compress(contents, ZSTD_LEVEL)?,
+        ContentEncoding::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::new(GZIP_LEVEL));
+            encoder.write_all(contents)?;
+            encoder.finish()?
+        }
+        _ => contents.to_vec(),
+    };
+
+    Ok(compressed)
+}
+
+/// Whether a file is a handlebars template, they're rendered along with their site.
+pub fn is_template(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "hbs")
+}
+
+/// Minify an HTML document, it's left as is if it fails to minify.
+pub fn minify_html(contents: Bytes) -> Bytes {
+    match minify_html_onepass::copy(&contents, &Cfg::new()) {
+        Ok(minified_html) => Bytes::from(minified_html),
+        Err(_) => contents,
+    }
+}
+
+/// Whether a file should be minified at build time, stylesheets with `minify_css` and scripts
+/// with `minify_js`. Files named like `app.min.js` are minified already.
+pub fn should_minify(path: &Path, mime_type: &str, config: &Config) -> bool {
+    let already_minified = path
+        .file_name()
+        .is_some_and(|name| name.to_string_lossy().contains(".min."));
+
+    !already_minified
+        && match mime_type {
+            "text/css" => config.minify_css,
+            "application/javascript" | "text/javascript" => config.minify_js,
+            _ => false,
+        }
+}
+
+/// Minify a stylesheet (`text/css`) or a script.
+pub fn minify(path: &Path, mime_type: &str, contents: &[u8]) -> Result<Vec<u8>> {
+    match mime_type {
+        "text/css" => minify_css(contents),
+        _ => minify_script(path, contents),
+    }
+}
+
+fn minify_css(contents: &[u8]) -> Result<Vec<u8>> {
+    let mut stylesheet =
+        StyleSheet::parse(std::str::from_utf8(contents)?, ParserOptions::default())
+            .map_err(|e| anyhow!("{}", e))?;
+
+    stylesheet
+        .minify(MinifyOptions::default())
+        .map_err(|e| anyhow!("{}", e))?;
+
+    let printer_options = PrinterOptions {
+        minify: true,
+        ..PrinterOptions::default()
+    };
+
+    Ok(stylesheet.to_css(printer_options)?.code.into_bytes())
+}
+
+fn minify_script(path: &Path, contents: &[u8]) -> Result<Vec<u8>> {
+    // ES modules are parsed as such, the rest as classic scripts
+    let top_level_mode = match path.extension().is_some_and(|ext| ext == "mjs") {
+        true => TopLevelMode::Module,
+        false => TopLevelMode::Global,
+    };
+
+    let session = Session::new();
+    let mut minified = Vec::new();
+
+    minify_js::minify(&session, top_level_mode, contents, &mut minified)
+        .map_err(|e| anyhow!("{}", e))?;
+
+    Ok(minified)
+}
+
+/// Whether a file is a precompressed sidecar of another one, like `app.js.br` of `app.js`.
+pub fn is_sidecar(path: &Path) -> bool {
+    SIDECARS
+        .iter()
+        .any(|(extension, _)| path.extension().is_some_and(|ext| ext == *extension))
+        && path.with_extension("").is_file()
+}
+
+/// The precompressed variant preferred by the request's `Accept-Encoding`, if any.
+///
+/// Without q-values brotli is preferred over zstd over gzip.
+pub fn preferred_variant<'a>(
+    variants: &'a [Variant],
+    req: &impl HttpMessage,
+) -> Option<&'a Variant> {
+    if variants.is_empty() {
+        return None;
+    }
+
+    let accept_encoding = AcceptEncoding::parse(req).ok()?;
+
+    let supported = variants
+        .iter()
+        .map(|variant| Encoding::Known(variant.encoding))
+        .chain([Encoding::identity()])
+        .collect::<Vec<Encoding>>();
+
+    match accept_encoding.negotiate(supported.iter())? {
+        Encoding::Known(encoding) if encoding != ContentEncoding::Identity => {
+            variants.iter().find(|variant| variant.encoding == encoding)
+        }
+        _ => None,
+    }
+}
+
+/// Generate the error pages by their status code, the 404 Not Found page defaults to the starter one.
+pub fn generate_error_pages(static_config: &Static) -> Result<Vec<(StatusCode, StaticFile)>> {
+    let mut error_pages = Vec::with_capacity(static_config.error_pages.len() + 1);
 
     // default not found template
-    let mut not_found_template = Bytes::from(include_bytes!("../starter/404.html").to_vec());
+    if !static_config.error_pages.contains_key(&404) {
+        error_pages.push((
+            StatusCode::NOT_FOUND,
+            StaticFile {
+                mime: Some(HeaderValue::from_static("text/html")),
+                bytes: Bytes::from(include_bytes!("../starter/404.html").to_vec()),
+                path: PathBuf::new(),
+                etag: None,
+                last_modified: None,
+                hbs_bytes: Bytes::new(),
+                minified_bytes: Bytes::new(),
+                variants: Vec::new(),
+            },
+        ));
+    }
 
     // user defined error pages
-    let error_page_map = &config.r#static.error_pages;
+    for (status_code, page_path) in &static_config.error_pages {
+        let status = u16::try_from(*status_code)
+            .ok()
+            .and_then(|status_code| StatusCode::from_u16(status_code).ok())
+            .filter(|status| status.is_client_error() || status.is_server_error());
 
-    // for defined error page templates
-    if !error_page_map.is_empty() {
-        if let Some(template_path) = error_page_map.get(&404) {
-            let read_file = fs::read(template_path).with_context(|| {
-                format!("Failed to read file {:?}", template_path.to_string_lossy())
-            })?;
-            not_found_template = Bytes::from(read_file)
-        }
+        let Some(status) = status else {
+            bail!("Invalid status code {} in `error_pages`", status_code);
+        };
+
+        error_pages.push((status, StaticFile::create_error_page(page_path)?));
     }
 
-    Ok(StaticFile {
-        mime: None,
-        bytes: not_found_template,
-        path: PathBuf::new(),
-        etag: None,
-        last_modified: None,
-        hbs_bytes: Bytes::new(),
-    })
+    Ok(error_pages)
 }
 
 /// Generate the starter boilerplate.
@@ -226,3 +689,30 @@ pub fn generate_starter_boilerplate() -> io::Result<()> {
 
     Ok(())
 }
+
+/// Write a file only readable by the current user.
+///
+/// It's created with those permissions, so the contents are never readable by others.
+pub fn write_private(path: &Path, contents: &str) -> Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options.open(path)?;
+
+    // the mode only applies to new files, an existing one is restricted before it's written
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    }
+
+    file.write_all(contents.as_bytes())?;
+
+    Ok(())
+}
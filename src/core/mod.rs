@@ -1,8 +1,17 @@
+pub(super) mod acme;
+pub(super) mod assets;
+pub(super) mod cache;
 pub(super) mod config;
+pub(super) mod dev_certs;
 pub(crate) mod engine;
 pub(super) mod files;
+pub(super) mod ocsp;
+pub(super) mod preconditions;
+pub(super) mod ranges;
 pub(super) mod routes;
 pub(super) mod server;
 pub(super) mod templates;
+#[cfg(test)]
+pub(super) mod test_utils;
 pub(super) mod tls;
 pub(super) mod watcher;
@@ -0,0 +1,705 @@
+use rustls::pki_types::CertificateDer;
+
+use x509_parser::{
+    extensions::{GeneralName, ParsedExtension},
+    oid_registry::OID_PKIX_ACCESS_DESCRIPTOR_OCSP,
+    prelude::{FromDer, X509Certificate},
+};
+
+use ring::digest::{digest, Algorithm, SHA1_FOR_LEGACY_USE_ONLY, SHA256};
+
+use time::{Date, Month, OffsetDateTime, PrimitiveDateTime, Time};
+
+use actix_web::{
+    rt::{time::timeout, Runtime},
+    web::Bytes,
+};
+
+use http::header::CONTENT_TYPE;
+
+use http_body_util::{BodyExt, Full, Limited};
+use hyper_rustls::HttpsConnectorBuilder;
+use hyper_util::{client::legacy::Client as HyperClient, rt::TokioExecutor};
+
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+
+/// Timeout of the requests to the OCSP responders
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Max size of an OCSP response
+const MAX_RESPONSE_SIZE: usize = 65_536;
+
+/// DER tags
+const INTEGER: u8 = 0x02;
+const OCTET_STRING: u8 = 0x04;
+const NULL: u8 = 0x05;
+const OBJECT_IDENTIFIER: u8 = 0x06;
+const ENUMERATED: u8 = 0x0a;
+const GENERALIZED_TIME: u8 = 0x18;
+const SEQUENCE: u8 = 0x30;
+const CONTEXT_0: u8 = 0xa0;
+
+/// SHA-1 (1.3.14.3.2.26), the `CertID` hash every OCSP responder supports
+const SHA1_OID: &[u8] = &[0x2b, 0x0e, 0x03, 0x02, 0x1a];
+
+/// SHA-256 (2.16.840.1.101.3.4.2.1), used in the `CertID` of some responses
+const SHA256_OID: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01];
+
+/// id-pkix-ocsp-basic (1.3.6.1.5.5.7.48.1.1), the only response type there is
+const OCSP_BASIC_OID: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x01, 0x01];
+
+/// How long a response without a `nextUpdate` is considered current
+const DEFAULT_VALIDITY: time::Duration = time::Duration::days(1);
+
+/// Read a pre-fetched OCSP response, DER encoded like `openssl ocsp -respout` writes it.
+pub fn read_response(path: &Path, cert_chain: &[CertificateDer]) -> Result<Vec<u8>> {
+    let response = fs::read(path)
+        .with_context(|| format!("Failed to read file {:?}", path.to_string_lossy()))?;
+
+    check_response(&response, cert_chain)
+        .with_context(|| format!("Invalid OCSP response {:?}", path.to_string_lossy()))?;
+
+    Ok(response)
+}
+
+/// Fetch the OCSP response of a certificate from the responder in its AIA extension.
+///
+/// The issuer has to be the second certificate of the chain.
+pub fn fetch_response(cert_chain: &[CertificateDer]) -> Result<Vec<u8>> {
+    let (cert, issuer) = certificate_and_issuer(cert_chain)?;
+
+    let responder = responder_url(&cert)
+        .context("The certificate has no OCSP responder (Authority Information Access)")?;
+
+    let request = http::Request::post(responder)
+        .header(CONTENT_TYPE, "application/ocsp-request")
+        .body(Full::new(Bytes::from(request(&cert, &issuer))))?;
+
+    // the certificates are loaded by synchronous code (on the runtime of the server at startup),
+    // so the request is sent from a runtime of its own
+    let body = std::thread::scope(|scope| {
+        scope
+            .spawn(|| {
+                Runtime::new()?
+                    .block_on(async { timeout(REQUEST_TIMEOUT, post(request)).await })
+                    .context("The responder didn't answer in time")?
+            })
+            .join()
+            .expect("the OCSP request panicked")
+    })
+    .with_context(|| format!("OCSP request to {} failed", responder))?;
+
+    check_response(&body, cert_chain)
+        .with_context(|| format!("Invalid OCSP response from {}", responder))?;
+
+    Ok(body.to_vec())
+}
+
+/// Send an OCSP request, returns the body of the response.
+async fn post(request: http::Request<Full<Bytes>>) -> Result<Bytes> {
+    // the responders are usually served over plain HTTP, the responses are signed
+    let connector = HttpsConnectorBuilder::new()
+        .with_native_roots()?
+        .https_or_http()
+        .enable_http1()
+        .build();
+
+    let response = HyperClient::builder(TokioExecutor::new())
+        .build(connector)
+        .request(request)
+        .await?;
+
+    if !response.status().is_success() {
+        bail!("The responder answered with {}", response.status());
+    }
+
+    let body = Limited::new(response.into_body(), MAX_RESPONSE_SIZE)
+        .collect()
+        .await
+        .map_err(|e| anyhow!(e))?;
+
+    Ok(body.to_bytes())
+}
+
+/// Parse the certificate and its issuer, the first two certificates of the chain.
+fn certificate_and_issuer<'a>(
+    cert_chain: &'a [CertificateDer],
+) -> Result<(X509Certificate<'a>, X509Certificate<'a>)> {
+    let [cert, issuer, ..] = cert_chain else {
+        bail!("The certificate chain has no issuer certificate, it's needed for OCSP");
+    };
+
+    let (_, cert) = X509Certificate::from_der(cert)?;
+    let (_, issuer) = X509Certificate::from_der(issuer)?;
+
+    Ok((cert, issuer))
+}
+
+/// The OCSP responder URL of the Authority Information Access extension.
+fn responder_url<'a>(cert: &X509Certificate<'a>) -> Option<&'a str> {
+    cert.extensions()
+        .iter()
+        .find_map(|extension| match extension.parsed_extension() {
+            ParsedExtension::AuthorityInfoAccess(aia) => {
+                aia.accessdescs.iter().find_map(|access| {
+                    match (&access.access_method, &access.access_location) {
+                        (method, GeneralName::URI(uri))
+                            if *method == OID_PKIX_ACCESS_DESCRIPTOR_OCSP =>
+                        {
+                            Some(*uri)
+                        }
+                        _ => None,
+                    }
+                })
+            }
+            _ => None,
+        })
+}
+
+/// Encode an OCSP request for a single certificate. (RFC 6960 §4.1.1)
+fn request(cert: &X509Certificate, issuer: &X509Certificate) -> Vec<u8> {
+    let issuer_name_hash = digest(&SHA1_FOR_LEGACY_USE_ONLY, cert.issuer().as_raw());
+    let issuer_key_hash = digest(
+        &SHA1_FOR_LEGACY_USE_ONLY,
+        &issuer.public_key().subject_public_key.data,
+    );
+
+    let hash_algorithm = der(
+        SEQUENCE,
+        &[der(OBJECT_IDENTIFIER, SHA1_OID), der(NULL, &[])].concat(),
+    );
+
+    let cert_id = der(
+        SEQUENCE,
+        &[
+            hash_algorithm,
+            der(OCTET_STRING, issuer_name_hash.as_ref()),
+            der(OCTET_STRING, issuer_key_hash.as_ref()),
+            der(INTEGER, cert.raw_serial()),
+        ]
+        .concat(),
+    );
+
+    // OCSPRequest { TBSRequest { requestList { Request { CertID } } } }
+    der(
+        SEQUENCE,
+        &der(SEQUENCE, &der(SEQUENCE, &der(SEQUENCE, &cert_id))),
+    )
+}
+
+/// Check an OCSP response before stapling it, it has to be successful, about the
+/// first certificate of the chain and not expired yet. (RFC 6960 §4.2.1)
+///
+/// The signature is left to the clients, they check it against the issuer anyway.
+pub fn check_response(response: &[u8], cert_chain: &[CertificateDer]) -> Result<()> {
+    let (cert, issuer) = certificate_and_issuer(cert_chain)?;
+
+    let Some((SEQUENCE, response, _)) = der_read(response) else {
+        bail!("Malformed OCSP response");
+    };
+
+    let Some((ENUMERATED, status, response_bytes)) = der_read(response) else {
+        bail!("Malformed OCSP response");
+    };
+
+    let status = match status {
+        [0] => "successful",
+        [1] => "malformedRequest",
+        [2] => "internalError",
+        [3] => "tryLater",
+        [5] => "sigRequired",
+        [6] => "unauthorized",
+        _ => "unknown",
+    };
+
+    if status != "successful" {
+        bail!("The OCSP responder returned the `{}` status", status);
+    }
+
+    // ResponseBytes { responseType, response }
+    let Some((CONTEXT_0, response_bytes, _)) = der_read(response_bytes) else {
+        bail!("Malformed OCSP response");
+    };
+
+    let Some((SEQUENCE, response_bytes, _)) = der_read(response_bytes) else {
+        bail!("Malformed OCSP response");
+    };
+
+    let Some((OBJECT_IDENTIFIER, OCSP_BASIC_OID, basic_response)) = der_read(response_bytes) else {
+        bail!("Unsupported OCSP response type");
+    };
+
+    // BasicOCSPResponse { tbsResponseData, signatureAlgorithm, signature, certs }
+    let Some((OCTET_STRING, basic_response, _)) = der_read(basic_response) else {
+        bail!("Malformed OCSP response");
+    };
+
+    let Some((SEQUENCE, basic_response, _)) = der_read(basic_response) else {
+        bail!("Malformed OCSP response");
+    };
+
+    let Some((SEQUENCE, response_data, _)) = der_read(basic_response) else {
+        bail!("Malformed OCSP response");
+    };
+
+    // ResponseData { version, responderID, producedAt, responses, responseExtensions },
+    // the responses are the only sequence in it
+    let responses = der_values(response_data)
+        .find_map(|(tag, responses)| (tag == SEQUENCE).then_some(responses))
+        .context("Malformed OCSP response")?;
+
+    // SingleResponse { certID, certStatus, thisUpdate, nextUpdate, singleExtensions }
+    let single_response = der_values(responses)
+        .filter(|(tag, _)| *tag == SEQUENCE)
+        .map(|(_, single_response)| der_values(single_response).collect::<Vec<_>>())
+        .find(|fields| {
+            matches!(fields.first(), Some((SEQUENCE, cert_id)) if cert_id_matches(cert_id, &cert, &issuer))
+        })
+        .with_context(|| {
+            format!(
+                "The OCSP response isn't about the certificate with the serial number {}",
+                cert.raw_serial_as_string()
+            )
+        })?;
+
+    let this_update = match single_response.get(2) {
+        Some((GENERALIZED_TIME, this_update)) => generalized_time(this_update),
+        _ => None,
+    }
+    .context("Malformed OCSP response")?;
+
+    let next_update = match single_response.get(3) {
+        Some((CONTEXT_0, next_update)) => match der_read(next_update) {
+            Some((GENERALIZED_TIME, next_update, _)) => {
+                Some(generalized_time(next_update).context("Malformed OCSP response")?)
+            }
+            _ => bail!("Malformed OCSP response"),
+        },
+        _ => None,
+    };
+
+    let expires = next_update.unwrap_or(this_update + DEFAULT_VALIDITY);
+
+    if expires <= OffsetDateTime::now_utc() {
+        bail!("The OCSP response expired on {}", expires);
+    }
+
+    Ok(())
+}
+
+/// Whether a `CertID` identifies the certificate, by its serial number and issuer.
+fn cert_id_matches(cert_id: &[u8], cert: &X509Certificate, issuer: &X509Certificate) -> bool {
+    let fields: Vec<_> = der_values(cert_id).collect();
+
+    let [(SEQUENCE, hash_algorithm), (OCTET_STRING, issuer_name_hash), (OCTET_STRING, issuer_key_hash), (INTEGER, serial)] =
+        fields[..]
+    else {
+        return false;
+    };
+
+    let algorithm: &Algorithm = match der_read(hash_algorithm) {
+        Some((OBJECT_IDENTIFIER, SHA1_OID, _)) => &SHA1_FOR_LEGACY_USE_ONLY,
+        Some((OBJECT_IDENTIFIER, SHA256_OID, _)) => &SHA256,
+        _ => return false,
+    };
+
+    serial == cert.raw_serial()
+        && issuer_name_hash == digest(algorithm, cert.issuer().as_raw()).as_ref()
+        && issuer_key_hash
+            == digest(algorithm, &issuer.public_key().subject_public_key.data).as_ref()
+}
+
+/// Decode a GeneralizedTime, in UTC like DER requires. (`YYYYMMDDHHMMSS[.fff]Z`)
+fn generalized_time(value: &[u8]) -> Option<OffsetDateTime> {
+    let value = std::str::from_utf8(value).ok()?.strip_suffix('Z')?;
+
+    let date_time = value.get(..14)?;
+
+    if !date_time.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+
+    let number = |from: usize, to: usize| date_time[from..to].parse::<u8>().ok();
+
+    let date = Date::from_calendar_date(
+        date_time[..4].parse().ok()?,
+        Month::try_from(number(4, 6)?).ok()?,
+        number(6, 8)?,
+    )
+    .ok()?;
+
+    let time = Time::from_hms(number(8, 10)?, number(10, 12)?, number(12, 14)?).ok()?;
+
+    Some(PrimitiveDateTime::new(date, time).assume_utc())
+}
+
+/// Encode a DER value.
+fn der(tag: u8, contents: &[u8]) -> Vec<u8> {
+    let mut value = Vec::with_capacity(contents.len() + 6);
+    value.push(tag);
+
+    if contents.len() < 0x80 {
+        value.push(contents.len() as u8);
+    } else {
+        // long form, the number of length bytes followed by the length
+        let length = contents.len().to_be_bytes();
+        let length = &length[length.iter().take_while(|&&byte| byte == 0).count()..];
+
+        value.push(0x80 | length.len() as u8);
+        value.extend_from_slice(length);
+    }
+
+    value.extend_from_slice(contents);
+    value
+}
+
+/// Decode a DER value, returns the tag, the contents and the rest of the input.
+fn der_read(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, input) = input.split_first()?;
+    let (&length, mut input) = input.split_first()?;
+
+    let length = if length < 0x80 {
+        length as usize
+    } else {
+        let count = (length & 0x7f) as usize;
+
+        if count == 0 || count > std::mem::size_of::<usize>() || input.len() < count {
+            return None;
+        }
+
+        let (length, rest) = input.split_at(count);
+        input = rest;
+
+        length
+            .iter()
+            .fold(0, |length, &byte| (length << 8) | byte as usize)
+    };
+
+    if input.len() < length {
+        return None;
+    }
+
+    let (contents, rest) = input.split_at(length);
+
+    Some((tag, contents, rest))
+}
+
+/// The values of a DER sequence, as tags and contents.
+fn der_values(mut input: &[u8]) -> impl Iterator<Item = (u8, &[u8])> {
+    std::iter::from_fn(move || {
+        let (tag, contents, rest) = der_read(input)?;
+        input = rest;
+
+        Some((tag, contents))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rcgen::{Certificate, CertificateParams, CustomExtension, KeyPair};
+
+    use rustls::sign::CertifiedKey;
+
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::Arc;
+
+    use crate::core::config::Ocsp;
+    use crate::core::test_utils::{private_ca, write_temp};
+    use crate::core::tls::{certified_key_from_pem, staple_ocsp};
+
+    /// id-ad-ocsp (1.3.6.1.5.5.7.48.1)
+    const ID_AD_OCSP_OID: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x01];
+
+    /// Answers an OCSP request, given the `CertID` it's about.
+    type Respond = Arc<dyn Fn(&[u8]) -> Vec<u8> + Send + Sync>;
+
+    /// Issue a certificate with the responder in its AIA extension, along with its chain.
+    fn issue(ca: &Certificate, ca_key: &KeyPair, responder: &str) -> CertifiedKey {
+        // AuthorityInfoAccessSyntax { AccessDescription { id-ad-ocsp, uniformResourceIdentifier } }
+        let access_description = [
+            der(OBJECT_IDENTIFIER, ID_AD_OCSP_OID),
+            der(0x86, responder.as_bytes()),
+        ]
+        .concat();
+
+        let mut params = CertificateParams::new(vec!["localhost".into()]).unwrap();
+        params.custom_extensions = vec![CustomExtension::from_oid_content(
+            &[1, 3, 6, 1, 5, 5, 7, 1, 1],
+            der(SEQUENCE, &der(SEQUENCE, &access_description)),
+        )];
+
+        let key_pair = KeyPair::generate().unwrap();
+        let cert = params.signed_by(&key_pair, ca, ca_key).unwrap();
+
+        certified_key_from_pem(
+            (cert.pem() + &ca.pem()).as_bytes(),
+            key_pair.serialize_pem().as_bytes(),
+        )
+        .unwrap()
+    }
+
+    /// The `CertID` of a certificate, as it's sent in the OCSP requests.
+    fn cert_id(cert_chain: &[CertificateDer]) -> Vec<u8> {
+        let (cert, issuer) = certificate_and_issuer(cert_chain).unwrap();
+        request_cert_id(&request(&cert, &issuer)).unwrap().to_vec()
+    }
+
+    /// The contents of the `CertID` in an OCSP request.
+    fn request_cert_id(request: &[u8]) -> Option<&[u8]> {
+        // OCSPRequest { TBSRequest { requestList { Request { CertID } } } }
+        let (_, tbs_request, _) = der_read(request)?;
+        let (_, request_list, _) = der_read(tbs_request)?;
+        let (_, request, _) = der_read(request_list)?;
+        let (_, cert_id, _) = der_read(request)?;
+        let (_, cert_id, _) = der_read(cert_id)?;
+
+        Some(cert_id)
+    }
+
+    fn encode_time(time: OffsetDateTime) -> Vec<u8> {
+        let time = format!(
+            "{:04}{:02}{:02}{:02}{:02}{:02}Z",
+            time.year(),
+            time.month() as u8,
+            time.day(),
+            time.hour(),
+            time.minute(),
+            time.second()
+        );
+
+        der(GENERALIZED_TIME, time.as_bytes())
+    }
+
+    /// A `good` OCSP response about a `CertID`, the signature is made up.
+    fn good_response(cert_id: &[u8], next_update: Option<OffsetDateTime>) -> Vec<u8> {
+        let now = OffsetDateTime::now_utc();
+
+        let single_response = [
+            der(SEQUENCE, cert_id),
+            der(0x80, &[]),
+            encode_time(now - time::Duration::hours(1)),
+            next_update
+                .map(|next_update| der(CONTEXT_0, &encode_time(next_update)))
+                .unwrap_or_default(),
+        ]
+        .concat();
+
+        let response_data = [
+            der(0xa2, &der(OCTET_STRING, &[0; 20])),
+            encode_time(now),
+            der(SEQUENCE, &der(SEQUENCE, &single_response)),
+        ]
+        .concat();
+
+        let basic_response = [
+            der(SEQUENCE, &response_data),
+            der(SEQUENCE, &der(OBJECT_IDENTIFIER, SHA256_OID)),
+            der(0x03, &[0, 0]),
+        ]
+        .concat();
+
+        let response_bytes = [
+            der(OBJECT_IDENTIFIER, OCSP_BASIC_OID),
+            der(OCTET_STRING, &der(SEQUENCE, &basic_response)),
+        ]
+        .concat();
+
+        der(
+            SEQUENCE,
+            &[
+                der(ENUMERATED, &[0]),
+                der(CONTEXT_0, &der(SEQUENCE, &response_bytes)),
+            ]
+            .concat(),
+        )
+    }
+
+    /// Stand in for an OCSP responder, returns its URL.
+    fn serve_responder(respond: Respond) -> String {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut reader = BufReader::new(&mut stream);
+
+                // the headers, the requests are sent with a `Content-Length`
+                let mut content_length = 0;
+                let mut line = String::new();
+
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    if let Some((name, value)) = line.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            content_length = value.trim().parse().unwrap();
+                        }
+                    }
+
+                    line.clear();
+                }
+
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+
+                let response = respond(request_cert_id(&body).unwrap());
+
+                let head = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/ocsp-response\r\n\
+                    Content-Length: {}\r\nConnection: close\r\n\r\n",
+                    response.len()
+                );
+
+                stream
+                    .write_all(&[head.as_bytes(), &response].concat())
+                    .unwrap();
+            }
+        });
+
+        format!("http://127.0.0.1:{}/", port)
+    }
+
+    fn tomorrow() -> Option<OffsetDateTime> {
+        Some(OffsetDateTime::now_utc() + time::Duration::days(1))
+    }
+
+    #[test]
+    fn fetches_the_response_of_the_certificate() {
+        let responder = serve_responder(Arc::new(|cert_id| good_response(cert_id, tomorrow())));
+
+        let (ca, ca_key) = private_ca();
+        let certified_key = issue(&ca, &ca_key, &responder);
+
+        let response = fetch_response(&certified_key.cert).unwrap();
+        assert!(check_response(&response, &certified_key.cert).is_ok());
+
+        // without a `nextUpdate`, it's current for a while after its `thisUpdate`
+        let response = good_response(&cert_id(&certified_key.cert), None);
+        assert!(check_response(&response, &certified_key.cert).is_ok());
+    }
+
+    #[test]
+    fn rejects_the_responses_about_other_certificates() {
+        let (ca, ca_key) = private_ca();
+        let other_cert_id = cert_id(&issue(&ca, &ca_key, "http://127.0.0.1/").cert);
+
+        let responder =
+            serve_responder(Arc::new(move |_| good_response(&other_cert_id, tomorrow())));
+
+        // another certificate of the same issuer
+        let certified_key = issue(&ca, &ca_key, &responder);
+        let error = fetch_response(&certified_key.cert).unwrap_err();
+        assert!(format!("{:#}", error).contains("isn't about the certificate"));
+
+        // the same serial number, from another issuer
+        let (other_ca, _) = private_ca();
+        let cert_chain = [certified_key.cert[0].to_owned(), other_ca.der().to_owned()];
+        let response = good_response(&cert_id(&certified_key.cert), tomorrow());
+        let error = check_response(&response, &cert_chain).unwrap_err();
+        assert!(error.to_string().contains("isn't about the certificate"));
+    }
+
+    #[test]
+    fn rejects_the_expired_responses() {
+        let responder = serve_responder(Arc::new(|cert_id| {
+            good_response(
+                cert_id,
+                Some(OffsetDateTime::now_utc() - time::Duration::minutes(1)),
+            )
+        }));
+
+        let (ca, ca_key) = private_ca();
+        let certified_key = issue(&ca, &ca_key, &responder);
+
+        let error = fetch_response(&certified_key.cert).unwrap_err();
+        assert!(format!("{:#}", error).contains("The OCSP response expired"));
+    }
+
+    #[test]
+    fn staples_the_previous_response_until_it_expires() {
+        // `tryLater`
+        let responder = serve_responder(Arc::new(|_| der(SEQUENCE, &der(ENUMERATED, &[3]))));
+
+        let (ca, ca_key) = private_ca();
+        let mut certified_key = issue(&ca, &ca_key, &responder);
+
+        let ocsp = Ocsp {
+            enable: true,
+            ..Ocsp::default()
+        };
+
+        let staple = |certified_key: &mut CertifiedKey, previous: CertifiedKey| {
+            staple_ocsp(
+                certified_key,
+                Path::new("cert.pem"),
+                Path::new(""),
+                &ocsp,
+                Some(Arc::new(previous)),
+            );
+        };
+
+        let current = good_response(&cert_id(&certified_key.cert), tomorrow());
+        let mut previous = certified_key.clone();
+        previous.ocsp = Some(current.to_owned());
+
+        staple(&mut certified_key, previous.clone());
+        assert_eq!(certified_key.ocsp, Some(current));
+
+        // not once it has expired
+        let expired = good_response(
+            &cert_id(&certified_key.cert),
+            Some(OffsetDateTime::now_utc() - time::Duration::minutes(1)),
+        );
+        previous.ocsp = Some(expired);
+
+        staple(&mut certified_key, previous.clone());
+        assert_eq!(certified_key.ocsp, None);
+
+        // nor if it's the response of another certificate
+        let mut other = issue(&ca, &ca_key, &responder);
+        other.ocsp = Some(good_response(&cert_id(&other.cert), tomorrow()));
+
+        staple(&mut certified_key, other);
+        assert_eq!(certified_key.ocsp, None);
+    }
+
+    #[test]
+    fn loads_the_certificate_once_the_response_file_expires() {
+        let (ca, ca_key) = private_ca();
+        let mut certified_key = issue(&ca, &ca_key, "http://127.0.0.1:9/");
+
+        let cert_id = cert_id(&certified_key.cert);
+        let expired = good_response(
+            &cert_id,
+            Some(OffsetDateTime::now_utc() - time::Duration::minutes(1)),
+        );
+        let response_file = write_temp("expired.ocsp", expired);
+
+        let staple = |certified_key: &mut CertifiedKey, previous: Option<CertifiedKey>| {
+            staple_ocsp(
+                certified_key,
+                Path::new("cert.pem"),
+                &response_file.0,
+                &Ocsp::default(),
+                previous.map(Arc::new),
+            );
+        };
+
+        // served without a response
+        staple(&mut certified_key, None);
+        assert_eq!(certified_key.ocsp, None);
+
+        // or with the previous one while it's current
+        let current = good_response(&cert_id, tomorrow());
+        let mut previous = certified_key.clone();
+        previous.ocsp = Some(current.to_owned());
+
+        staple(&mut certified_key, Some(previous));
+        assert_eq!(certified_key.ocsp, Some(current));
+    }
+}
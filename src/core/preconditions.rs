@@ -0,0 +1,270 @@
+use actix_web::{
+    http::{
+        header::{
+            EntityTag, Header, HeaderValue, HttpDate, IfMatch, IfModifiedSince, IfNoneMatch,
+            IfUnmodifiedSince, IF_MATCH, IF_NONE_MATCH,
+        },
+        Method,
+    },
+    HttpRequest,
+};
+
+/// Outcome of the preconditions of a request. (RFC 9110 §13.2.2)
+#[derive(Debug, PartialEq)]
+pub enum Precondition {
+    /// respond as usual
+    Passed,
+    /// `304 Not Modified`, the representation cached by the client is current
+    NotModified,
+    /// `412 Precondition Failed`
+    Failed,
+}
+
+/// Evaluate `If-Match`, `If-Unmodified-Since`, `If-None-Match` and `If-Modified-Since`
+/// in the order (and with the precedence) defined by RFC 9110 §13.2.2.
+///
+/// Invalid dates are ignored, `If-Range` is evaluated along with the `Range` (see `ranges.rs`).
+pub fn evaluate(
+    req: &HttpRequest,
+    etag: &HeaderValue,
+    last_modified: &HeaderValue,
+) -> Precondition {
+    let etag = etag
+        .to_str()
+        .ok()
+        .and_then(|etag| etag.parse::<EntityTag>().ok());
+    let last_modified = last_modified
+        .to_str()
+        .ok()
+        .and_then(|last_modified| last_modified.parse::<HttpDate>().ok());
+
+    let headers = req.headers();
+    let is_get_or_head = matches!(*req.method(), Method::GET | Method::HEAD);
+
+    // `If-Match` uses the strong comparison, `If-Unmodified-Since` only applies without it
+    if headers.contains_key(IF_MATCH) {
+        let matches = match IfMatch::parse(req) {
+            Ok(IfMatch::Any) => true,
+            Ok(IfMatch::Items(tags)) => etag
+                .as_ref()
+                .is_some_and(|etag| tags.iter().any(|tag| tag.strong_eq(etag))),
+            Err(_) => false,
+        };
+
+        if !matches {
+            return Precondition::Failed;
+        }
+    } else if let Ok(IfUnmodifiedSince(since)) = IfUnmodifiedSince::parse(req) {
+        if last_modified.is_some_and(|last_modified| last_modified > since) {
+            return Precondition::Failed;
+        }
+    }
+
+    // `If-None-Match` uses the weak comparison, `If-Modified-Since` only applies without it
+    if headers.contains_key(IF_NONE_MATCH) {
+        let matches = match IfNoneMatch::parse(req) {
+            Ok(IfNoneMatch::Any) => true,
+            Ok(IfNoneMatch::Items(tags)) => etag
+                .as_ref()
+                .is_some_and(|etag| tags.iter().any(|tag| tag.weak_eq(etag))),
+            Err(_) => false,
+        };
+
+        if matches {
+            return match is_get_or_head {
+                true => Precondition::NotModified,
+                false => Precondition::Failed,
+            };
+        }
+    } else if is_get_or_head {
+        if let Ok(IfModifiedSince(since)) = IfModifiedSince::parse(req) {
+            if last_modified.is_some_and(|last_modified| last_modified <= since) {
+                return Precondition::NotModified;
+            }
+        }
+    }
+
+    Precondition::Passed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use actix_web::{
+        http::header::{IF_MODIFIED_SINCE, IF_UNMODIFIED_SINCE},
+        test::TestRequest,
+    };
+
+    const ETAG: &str = "\"abc\"";
+    const LAST_MODIFIED: &str = "Wed, 21 Oct 2015 07:28:00 GMT";
+
+    type Headers<'a> = &'a [(&'a str, &'a str)];
+
+    const EARLIER: &str = "Tue, 20 Oct 2015 07:28:00 GMT";
+    const LATER: &str = "Thu, 22 Oct 2015 07:28:00 GMT";
+
+    fn evaluate_request(method: Method, headers: Headers) -> Precondition {
+        let mut req = TestRequest::default().method(method);
+
+        for &header in headers {
+            req = req.insert_header(header);
+        }
+
+        evaluate(
+            &req.to_http_request(),
+            &HeaderValue::from_static(ETAG),
+            &HeaderValue::from_static(LAST_MODIFIED),
+        )
+    }
+
+    #[test]
+    fn evaluates_if_match_and_if_unmodified_since() {
+        let if_match = IF_MATCH.as_str();
+        let if_unmodified_since = IF_UNMODIFIED_SINCE.as_str();
+
+        let cases: &[(Headers, Precondition)] = &[
+            (&[], Precondition::Passed),
+            (&[(if_match, "\"abc\"")], Precondition::Passed),
+            (&[(if_match, "\"xyz\", \"abc\"")], Precondition::Passed),
+            (&[(if_match, "*")], Precondition::Passed),
+            (&[(if_match, "\"xyz\"")], Precondition::Failed),
+            // the strong comparison
+            (&[(if_match, "W/\"abc\"")], Precondition::Failed),
+            (
+                &[(if_unmodified_since, LAST_MODIFIED)],
+                Precondition::Passed,
+            ),
+            (&[(if_unmodified_since, LATER)], Precondition::Passed),
+            (&[(if_unmodified_since, EARLIER)], Precondition::Failed),
+            // invalid dates are ignored
+            (&[(if_unmodified_since, "yesterday")], Precondition::Passed),
+            // `If-Unmodified-Since` only applies without `If-Match`
+            (
+                &[(if_match, "\"abc\""), (if_unmodified_since, EARLIER)],
+                Precondition::Passed,
+            ),
+        ];
+
+        for (headers, expected) in cases {
+            for method in [Method::GET, Method::POST] {
+                assert_eq!(
+                    evaluate_request(method.to_owned(), headers),
+                    *expected,
+                    "{} {:?}",
+                    method,
+                    headers
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn evaluates_if_none_match_and_if_modified_since() {
+        let if_match = IF_MATCH.as_str();
+        let if_none_match = IF_NONE_MATCH.as_str();
+        let if_modified_since = IF_MODIFIED_SINCE.as_str();
+
+        let cases: &[(Method, Headers, Precondition)] = &[
+            (
+                Method::GET,
+                &[(if_none_match, "\"abc\"")],
+                Precondition::NotModified,
+            ),
+            (
+                Method::HEAD,
+                &[(if_none_match, "\"abc\"")],
+                Precondition::NotModified,
+            ),
+            (
+                Method::GET,
+                &[(if_none_match, "\"xyz\", \"abc\"")],
+                Precondition::NotModified,
+            ),
+            (
+                Method::GET,
+                &[(if_none_match, "*")],
+                Precondition::NotModified,
+            ),
+            // the weak comparison
+            (
+                Method::GET,
+                &[(if_none_match, "W/\"abc\"")],
+                Precondition::NotModified,
+            ),
+            (
+                Method::GET,
+                &[(if_none_match, "\"xyz\"")],
+                Precondition::Passed,
+            ),
+            (
+                Method::POST,
+                &[(if_none_match, "\"abc\"")],
+                Precondition::Failed,
+            ),
+            (
+                Method::POST,
+                &[(if_none_match, "\"xyz\"")],
+                Precondition::Passed,
+            ),
+            (
+                Method::GET,
+                &[(if_modified_since, LAST_MODIFIED)],
+                Precondition::NotModified,
+            ),
+            (
+                Method::GET,
+                &[(if_modified_since, LATER)],
+                Precondition::NotModified,
+            ),
+            (
+                Method::GET,
+                &[(if_modified_since, EARLIER)],
+                Precondition::Passed,
+            ),
+            // the obsolete RFC 850 and asctime formats
+            (
+                Method::GET,
+                &[(if_modified_since, "Wednesday, 21-Oct-15 07:28:00 GMT")],
+                Precondition::NotModified,
+            ),
+            (
+                Method::GET,
+                &[(if_modified_since, "Wed Oct 21 07:28:00 2015")],
+                Precondition::NotModified,
+            ),
+            (
+                Method::GET,
+                &[(if_modified_since, "yesterday")],
+                Precondition::Passed,
+            ),
+            // `If-Modified-Since` only applies to GET and HEAD, and without `If-None-Match`
+            (
+                Method::POST,
+                &[(if_modified_since, LATER)],
+                Precondition::Passed,
+            ),
+            (
+                Method::GET,
+                &[(if_none_match, "\"xyz\""), (if_modified_since, LATER)],
+                Precondition::Passed,
+            ),
+            // `If-Match` is evaluated first
+            (
+                Method::GET,
+                &[(if_match, "\"xyz\""), (if_none_match, "\"abc\"")],
+                Precondition::Failed,
+            ),
+        ];
+
+        for (method, headers, expected) in cases {
+            assert_eq!(
+                evaluate_request(method.to_owned(), headers),
+                *expected,
+                "{} {:?}",
+                method,
+                headers
+            );
+        }
+    }
+}
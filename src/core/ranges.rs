@@ -0,0 +1,263 @@
+use actix_web::{
+    http::{
+        header::{EntityTag, Header, HeaderValue, HttpDate, IfRange, Range, CONTENT_RANGE},
+        Method, StatusCode,
+    },
+    web::{BufMut, Bytes, BytesMut},
+    HttpRequest, HttpResponse, HttpResponseBuilder,
+};
+
+use ring::rand::{SecureRandom, SystemRandom};
+
+use std::fmt::Write;
+
+/// Max number of ranges in a request, more than that gets the full response
+const MAX_RANGES: usize = 16;
+
+/// The byte ranges of a response requested by the client. (RFC 9110 §14)
+#[derive(Debug, PartialEq)]
+pub enum Ranges {
+    /// the full response, without a `Range` header (or one which doesn't apply)
+    Full,
+    /// the satisfiable ranges, the start and the end are inclusive
+    Partial(Vec<(u64, u64)>),
+    /// none of the ranges are satisfiable
+    Unsatisfiable,
+}
+
+/// The ranges requested by `Range`, if `If-Range` matches the current representation.
+pub fn requested(
+    req: &HttpRequest,
+    length: u64,
+    etag: &HeaderValue,
+    last_modified: &HeaderValue,
+) -> Ranges {
+    // ranges are only defined for GET
+    if req.method() != Method::GET {
+        return Ranges::Full;
+    }
+
+    // an invalid `Range` header or an unknown range unit is ignored
+    let Ok(Range::Bytes(specs)) = Range::parse(req) else {
+        return Ranges::Full;
+    };
+
+    if !if_range_matches(req, etag, last_modified) {
+        return Ranges::Full;
+    }
+
+    let ranges = specs
+        .iter()
+        .filter_map(|spec| spec.to_satisfiable_range(length))
+        .collect::<Vec<(u64, u64)>>();
+
+    if ranges.is_empty() {
+        return Ranges::Unsatisfiable;
+    }
+
+    // lots of (overlapping) ranges are more costly to serve than the full response
+    let requested_length: u64 = ranges.iter().map(|(start, end)| end - start + 1).sum();
+
+    if ranges.len() > MAX_RANGES || requested_length > length {
+        return Ranges::Full;
+    }
+
+    Ranges::Partial(ranges)
+}
+
+/// `If-Range` matches with a strong comparison of the etag or the exact last modified date.
+fn if_range_matches(req: &HttpRequest, etag: &HeaderValue, last_modified: &HeaderValue) -> bool {
+    // without `If-Range` the ranges always apply
+    if !req.headers().contains_key(IfRange::name()) {
+        return true;
+    }
+
+    match IfRange::parse(req) {
+        Ok(IfRange::EntityTag(if_range)) => etag
+            .to_str()
+            .ok()
+            .and_then(|etag| etag.parse::<EntityTag>().ok())
+            .is_some_and(|etag| etag.strong_eq(&if_range)),
+        Ok(IfRange::Date(if_range)) => last_modified
+            .to_str()
+            .ok()
+            .and_then(|last_modified| last_modified.parse::<HttpDate>().ok())
+            .is_some_and(|last_modified| last_modified == if_range),
+        Err(_) => false,
+    }
+}
+
+/// Respond with `416 Range Not Satisfiable`.
+pub fn not_satisfiable(mut response: HttpResponseBuilder, length: u64) -> HttpResponse {
+    response
+        .status(StatusCode::RANGE_NOT_SATISFIABLE)
+        .insert_header((CONTENT_RANGE, format!("bytes */{}", length)))
+        .finish()
+}
+
+/// Respond with `206 Partial Content`, multiple ranges are sent as `multipart/byteranges`.
+pub fn partial_content(
+    mut response: HttpResponseBuilder,
+    body: Bytes,
+    content_type: &HeaderValue,
+    ranges: &[(u64, u64)],
+) -> HttpResponse {
+    let length = body.len();
+
+    response.status(StatusCode::PARTIAL_CONTENT);
+
+    if let [(start, end)] = ranges {
+        return response
+            .insert_header((CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, length)))
+            .content_type(content_type)
+            .body(body.slice(*start as usize..=*end as usize));
+    }
+
+    let boundary = boundary();
+
+    let mut multipart = BytesMut::new();
+
+    for (start, end) in ranges {
+        let part_headers = format!(
+            "--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+            boundary,
+            content_type.to_str().unwrap_or("application/octet-stream"),
+            start,
+            end,
+            length
+        );
+
+        multipart.put_slice(part_headers.as_bytes());
+        multipart.put_slice(&body[*start as usize..=*end as usize]);
+        multipart.put_slice(b"\r\n");
+    }
+
+    multipart.put_slice(format!("--{}--\r\n", boundary).as_bytes());
+
+    response
+        .content_type(format!("multipart/byteranges; boundary={}", boundary))
+        .body(multipart.freeze())
+}
+
+/// A random `multipart` boundary, it can't be mistaken for the contents.
+fn boundary() -> String {
+    let mut random = [0u8; 16];
+    SystemRandom::new().fill(&mut random).unwrap_or_default();
+
+    random
+        .iter()
+        .fold(String::with_capacity(32), |mut hex, byte| {
+            let _ = write!(hex, "{:02x}", byte);
+            hex
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use actix_web::{
+        http::header::{IF_RANGE, RANGE},
+        test::TestRequest,
+    };
+
+    const ETAG: &str = "\"abc\"";
+    const LAST_MODIFIED: &str = "Wed, 21 Oct 2015 07:28:00 GMT";
+
+    /// The ranges requested of a 100 bytes long response.
+    fn requested_ranges(method: Method, headers: &[(&str, &str)]) -> Ranges {
+        let mut req = TestRequest::default().method(method);
+
+        for &header in headers {
+            req = req.insert_header(header);
+        }
+
+        requested(
+            &req.to_http_request(),
+            100,
+            &HeaderValue::from_static(ETAG),
+            &HeaderValue::from_static(LAST_MODIFIED),
+        )
+    }
+
+    #[test]
+    fn resolves_the_requested_ranges() {
+        let many_ranges = (0..=MAX_RANGES)
+            .map(|i| format!("{}-{}", i * 2, i * 2))
+            .collect::<Vec<_>>()
+            .join(",");
+        let many_ranges = format!("bytes={}", many_ranges);
+
+        let cases: &[(&str, Ranges)] = &[
+            ("bytes=0-9", Ranges::Partial(vec![(0, 9)])),
+            ("bytes=90-", Ranges::Partial(vec![(90, 99)])),
+            // suffixes
+            ("bytes=-10", Ranges::Partial(vec![(90, 99)])),
+            ("bytes=-200", Ranges::Partial(vec![(0, 99)])),
+            // the end is past the last byte
+            ("bytes=95-200", Ranges::Partial(vec![(95, 99)])),
+            ("bytes=0-9,20-29", Ranges::Partial(vec![(0, 9), (20, 29)])),
+            // the unsatisfiable ones are left out
+            ("bytes=0-9,200-300", Ranges::Partial(vec![(0, 9)])),
+            ("bytes=100-", Ranges::Unsatisfiable),
+            ("bytes=200-300", Ranges::Unsatisfiable),
+            // more than the full response
+            ("bytes=0-59,40-99", Ranges::Full),
+            (&many_ranges, Ranges::Full),
+            // invalid or unknown units are ignored
+            ("bytes=abc", Ranges::Full),
+            ("items=0-9", Ranges::Full),
+        ];
+
+        for (range, expected) in cases {
+            assert_eq!(
+                requested_ranges(Method::GET, &[(RANGE.as_str(), range)]),
+                *expected,
+                "{}",
+                range
+            );
+        }
+
+        assert_eq!(requested_ranges(Method::GET, &[]), Ranges::Full);
+
+        // ranges are only defined for GET
+        for method in [Method::HEAD, Method::POST] {
+            assert_eq!(
+                requested_ranges(method, &[(RANGE.as_str(), "bytes=0-9")]),
+                Ranges::Full
+            );
+        }
+    }
+
+    #[test]
+    fn applies_the_ranges_if_the_representation_matches() {
+        let cases: &[(&str, bool)] = &[
+            ("\"abc\"", true),
+            ("\"xyz\"", false),
+            // the strong comparison
+            ("W/\"abc\"", false),
+            (LAST_MODIFIED, true),
+            // the exact date only
+            ("Tue, 20 Oct 2015 07:28:00 GMT", false),
+            ("Thu, 22 Oct 2015 07:28:00 GMT", false),
+            ("yesterday", false),
+        ];
+
+        for (if_range, matches) in cases {
+            let expected = match matches {
+                true => Ranges::Partial(vec![(0, 9)]),
+                false => Ranges::Full,
+            };
+
+            assert_eq!(
+                requested_ranges(
+                    Method::GET,
+                    &[(RANGE.as_str(), "bytes=0-9"), (IF_RANGE.as_str(), if_range)]
+                ),
+                expected,
+                "{}",
+                if_range
+            );
+        }
+    }
+}
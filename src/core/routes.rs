@@ -1,15 +1,28 @@
-use dashmap::DashMap;
+use dashmap::{DashMap, DashSet};
 use once_cell::sync::Lazy;
 
 /// routes are usually small in size, store them in the stack
 use compact_str::CompactString;
 
-use std::collections::HashMap;
+use std::collections::HashSet;
 use std::path::PathBuf;
+use std::sync::Arc;
+
+use actix_web::{
+    dev::RequestHead,
+    http::{header::HOST, StatusCode},
+};
 
 // multi-threaded directory walking
 use jwalk::WalkDir;
 
+use super::assets::{self, Asset, ASSETS};
+use super::cache::CACHE;
+use super::config::{BinserveConfig, Config, Site, Static};
+use super::templates;
+
+use crate::cli::messages::{push_message, Type as MsgType};
+
 /// Route type indicating whether the file is read from memory or disk
 #[derive(Debug, PartialEq)]
 pub enum Type {
@@ -18,7 +31,7 @@ pub enum Type {
 }
 
 /// Represents a static file, both in-memory and from disk
-use super::files::{generate_not_found, StaticFile};
+use super::files::{self, generate_error_pages, StaticFile};
 
 /// Struct to contain and handle the Response type for the route.
 #[derive(Debug)]
@@ -32,23 +45,314 @@ use ahash::RandomState;
 
 /// A concurrent HashMap containing all the routes and the bytes to it's corresponding files.
 /// Files are read at initialization so as to prevent I/O operations at runtime (only when `fast_mem_cache` is enabled)
+///
+/// Routes are keyed by the site name followed by the path (see `route_key`).
 pub static ROUTEMAP: Lazy<DashMap<CompactString, RouteHandle, RandomState>> =
     Lazy::new(|| DashMap::with_hasher(RandomState::new()));
 
+/// The host names (or `*.` wildcards) of the configured sites.
+pub static SITES: Lazy<DashSet<CompactString, RandomState>> =
+    Lazy::new(|| DashSet::with_hasher(RandomState::new()));
+
+/// The `spa_fallback` route keys of a site by their prefix, the longest prefix first
+type SpaFallbacks = Vec<(String, CompactString)>;
+
+/// The single-page applications of each site
+static SPA_FALLBACKS: Lazy<DashMap<CompactString, SpaFallbacks, RandomState>> =
+    Lazy::new(|| DashMap::with_hasher(RandomState::new()));
+
+/// The `.html` routes redirected to their clean URL (`clean_urls_redirect`), by route key.
+pub static REDIRECTS: Lazy<DashMap<CompactString, CompactString, RandomState>> =
+    Lazy::new(|| DashMap::with_hasher(RandomState::new()));
+
+/// How the error page templates of a site are rendered, per request
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ErrorPageSettings {
+    pub minify_html: bool,
+}
+
+/// The error page settings of each site, so the error responses don't need the config state.
+pub static ERROR_PAGE_SETTINGS: Lazy<DashMap<CompactString, ErrorPageSettings, RandomState>> =
+    Lazy::new(|| DashMap::with_hasher(RandomState::new()));
+
+/// Key of a route in the `ROUTEMAP`, the default site is named `""`.
+///
+/// Host names can't contain a `/` or a `{`, so the site and the
+/// route/error page key never bleed into each other.
+pub fn route_key(site: &str, route: &str) -> CompactString {
+    let mut key = CompactString::with_capacity(site.len() + route.len());
+    key.push_str(site);
+    key.push_str(route);
+    key
+}
+
+/// The route as it's stored in the routemap, with single slashes and a leading one.
+///
+/// The routes of the files under a directory route (`"/blog": "public/blog"`) are joined
+/// with a slash, `/blog//post.html` is stored as `/blog/post.html`.
+fn route_path(route: &str) -> String {
+    let mut route_str = String::from("/");
+
+    // this is to normalize multiple slashes
+    for segment in route.split('/').filter(|segment| !segment.is_empty()) {
+        if !route_str.ends_with('/') {
+            route_str.push('/');
+        }
+
+        route_str.push_str(segment);
+    }
+
+    // the trailing slash is part of the route
+    if route.ends_with('/') && !route_str.ends_with('/') {
+        route_str.push('/');
+    }
+
+    route_str
+}
+
+/// Key of the error page of a site for a status code, e.g. `{{404}}`.
+pub fn error_page_key(site: &str, status: StatusCode) -> CompactString {
+    route_key(site, &format!("{{{{{}}}}}", status.as_u16()))
+}
+
+/// Split a `ROUTEMAP` key back into the site name and the route.
+pub fn split_route_key(key: &str) -> (&str, &str) {
+    key.split_at(key.find(['/', '{']).unwrap_or(key.len()))
+}
+
+/// Key of the route serving the unknown paths under a single-page application (`spa_fallback`).
+///
+/// Paths with a file extension (e.g. `/app/main.js`) are missing files, they're left to 404.
+pub fn spa_fallback(site: &str, path: &str) -> Option<CompactString> {
+    let file_name = path.rsplit('/').next().unwrap_or_default();
+
+    if file_name.contains('.') {
+        return None;
+    }
+
+    SPA_FALLBACKS
+        .get(site)?
+        .iter()
+        .find(|(prefix, _)| {
+            path.strip_prefix(prefix.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        })
+        .map(|(_, key)| key.to_owned())
+}
+
+/// The request path the way the static file service resolves it to a file, percent-decoded
+/// and without the empty, `.` and `..` segments (`/a/%62/../c` is `/a/c`).
+pub fn normalize_path(path: &str) -> String {
+    let path = percent_encoding::percent_decode_str(path).decode_utf8_lossy();
+
+    let mut segments = Vec::new();
+
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+
+    format!("/{}", segments.join("/"))
+}
+
+/// Host of the request, from the `Host` header or the URI (HTTP/2).
+pub fn request_host(head: &RequestHead) -> &str {
+    head.headers
+        .get(HOST)
+        .and_then(|host| host.to_str().ok())
+        .or_else(|| head.uri.host())
+        .unwrap_or_default()
+}
+
+/// Match a host against the configured sites.
+///
+/// Exact host names take precedence over wildcard subdomains (the most specific
+/// one wins) and hosts not matching any of the sites get the default site (`""`).
+pub fn resolve_site(host: &str) -> CompactString {
+    if SITES.is_empty() {
+        return CompactString::default();
+    }
+
+    // strip the port and normalize the case
+    let host = host.split(':').next().unwrap_or_default();
+    let host = CompactString::from(host.to_ascii_lowercase());
+
+    if SITES.contains(&host) {
+        return host;
+    }
+
+    let mut parent = host.as_str();
+
+    while let Some((_, domain)) = parent.split_once('.') {
+        let wildcard = route_key("*.", domain);
+
+        if SITES.contains(&wildcard) {
+            return wildcard;
+        }
+
+        parent = domain;
+    }
+
+    CompactString::default()
+}
+
 /// Manages routes and it's corresponding responses
 impl RouteHandle {
-    /// Add routes to the concurrent hashmap containing the routes.
+    /// Add the routes of the default site and all of the virtual hosts.
+    pub fn add_sites(config: &BinserveConfig) -> anyhow::Result<()> {
+        let default_site = config.default_site();
+
+        // prepare template partials
+        let handlebars_handle = Self::prepare_site("", &default_site, &config.config)?;
+
+        // all of the routes are created again, they start out uncached with a memory budget
+        CACHE.reset(config.cache.max_memory);
+
+        // prepare routes table
+        let mut keys = Self::add_routes("", &default_site, &config.config, &handlebars_handle)?;
+
+        // forget the sites which were removed from the configuration
+        SITES.retain(|site| config.sites.contains_key(site.as_str()));
+        REDIRECTS.retain(|key, _| {
+            let (site, _) = split_route_key(key);
+            site.is_empty() || SITES.contains(site)
+        });
+        SPA_FALLBACKS.retain(|site, _| site.is_empty() || SITES.contains(site));
+        ERROR_PAGE_SETTINGS.retain(|site, _| site.is_empty() || SITES.contains(site));
+
+        for (name, site) in &config.sites {
+            let handlebars_handle = Self::prepare_site(name, site, &config.config)?;
+
+            keys.extend(Self::add_routes(
+                name,
+                site,
+                &config.config,
+                &handlebars_handle,
+            )?);
+
+            SITES.insert(name.into());
+        }
+
+        // forget the routes which were removed from the configuration (or along with their site),
+        // the error pages are kept up to date by their site
+        let stale_keys: Vec<CompactString> = ROUTEMAP
+            .iter()
+            .map(|route| route.key().to_owned())
+            .filter(|key| {
+                let (site_name, route) = split_route_key(key);
+
+                !keys.contains(key)
+                    && !(route.starts_with("{{")
+                        && (site_name.is_empty() || SITES.contains(site_name)))
+            })
+            .collect();
+
+        for key in stale_keys {
+            ROUTEMAP.remove(&key);
+            CACHE.remove(&key);
+        }
+
+        Ok(())
+    }
+
+    /// Collect (and fingerprint) the static files of a site and prepare its template partials.
+    fn prepare_site(
+        site_name: &str,
+        site: &Site,
+        config: &Config,
+    ) -> anyhow::Result<templates::HandlebarsHandle> {
+        let assets = Arc::new(assets::collect(&site.r#static, config)?);
+
+        // forget the routes of the files which have changed (or been removed) since the last build
+        if let Some(previous_assets) = ASSETS.insert(site_name.into(), assets.clone()) {
+            let urls = assets
+                .values()
+                .flat_map(Asset::routes)
+                .collect::<HashSet<&str>>();
+
+            for url in previous_assets.values().flat_map(Asset::routes) {
+                if !urls.contains(url) {
+                    let key = route_key(site_name, url);
+
+                    ROUTEMAP.remove(&key);
+                    CACHE.forget(&key);
+                }
+            }
+        }
+
+        let handlebars_handle = templates::render_templates(&site.template, assets)?;
+
+        templates::SITE_TEMPLATES.insert(site_name.into(), handlebars_handle.clone());
+
+        Ok(handlebars_handle)
+    }
+
+    /// Collect the static files of a site again and render its templates with them,
+    /// as they refer to the files with `{{asset}}` and `{{sri}}`.
+    pub fn reload_assets(site_name: &str, site: &Site, config: &Config) -> anyhow::Result<()> {
+        let handlebars_handle = Self::prepare_site(site_name, site, config)?;
+
+        // the routes of the templates
+        let templates: Vec<(String, PathBuf)> = ROUTEMAP
+            .iter()
+            .filter(|route| {
+                split_route_key(route.key()).0 == site_name
+                    && files::is_template(&route.response.path)
+            })
+            .map(|route| {
+                let (_, route_index) = split_route_key(route.key());
+                (route_index.to_owned(), route.response.path.to_owned())
+            })
+            .collect();
+
+        for (route, path) in templates {
+            Self::associate_files_to_routes(site_name, &route, &path, &handlebars_handle)?;
+        }
+
+        // the routes of the minified files and of the fingerprinted files which are new
+        for asset in assets::site_assets(site_name).values() {
+            for url in asset.routes() {
+                if url == asset.plain_url || !ROUTEMAP.contains_key(&route_key(site_name, url)) {
+                    Self::associate_asset_to_route(
+                        site_name,
+                        url,
+                        &asset.path,
+                        &handlebars_handle,
+                    )?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Add routes to the concurrent hashmap containing the routes, returns their keys.
     pub fn add_routes(
-        route_set: &HashMap<String, PathBuf>,
+        site_name: &str,
+        site: &Site,
+        config: &Config,
         handlebars_handle: &(handlebars::Handlebars, handlebars::Context),
-    ) -> anyhow::Result<()> {
-        for (route, path) in route_set {
+    ) -> anyhow::Result<HashSet<CompactString>> {
+        let mut keys = HashSet::new();
+
+        // the redirects are made again along with the routes
+        REDIRECTS.retain(|key, _| split_route_key(key).0 != site_name);
+
+        for (route, path) in &site.routes {
             if path.is_dir() {
                 // create a route entry for each file where the file path
                 // becomes the route just like a barebones static web server.
                 // simply serving a directory as is.
                 let starting_directory: String = path.to_string_lossy().into();
 
+                // the `.html` files served at their clean URL (`clean_urls`)
+                let mut clean_routes = Vec::new();
+
                 for entry in WalkDir::new(path) {
                     // the route is going to be the suffix of the defined file path
                     // in the configuration.
@@ -80,49 +384,203 @@ impl RouteHandle {
                             route_index = route_index.replace("/index", "")
                         }
 
-                        Self::associate_files_to_routes(
+                        // serve `about.html` as `/about` as well, or only as `/about`
+                        // with `/about.html` redirected to it
+                        if config.clean_urls && route_index.ends_with(".html") {
+                            if !config.clean_urls_redirect {
+                                keys.insert(Self::associate_files_to_routes(
+                                    site_name,
+                                    &route_index,
+                                    &entry.path(),
+                                    handlebars_handle,
+                                )?);
+                            }
+
+                            clean_routes.push((route_index, entry.path()));
+                            continue;
+                        }
+
+                        keys.insert(Self::associate_files_to_routes(
+                            site_name,
                             &route_index,
                             &entry.path(),
                             handlebars_handle,
-                        )?
+                        )?);
+                    }
+                }
+
+                // the directory is walked in parallel, so the clean routes are created once it's
+                // done to have `about/index.html` served at `/about` rather than `about.html`
+                for (route_index, file_path) in clean_routes {
+                    let clean_route =
+                        route_path(route_index.strip_suffix(".html").unwrap_or(&route_index));
+
+                    if keys.contains(&route_key(site_name, &clean_route)) {
+                        push_message(
+                            MsgType::Warning,
+                            &format!(
+                                "{:?} collides with the index file of {:?}, serving it at {:?} only",
+                                file_path.to_string_lossy(),
+                                clean_route,
+                                route_path(&route_index)
+                            ),
+                        );
+
+                        if config.clean_urls_redirect {
+                            keys.insert(Self::associate_files_to_routes(
+                                site_name,
+                                &route_index,
+                                &file_path,
+                                handlebars_handle,
+                            )?);
+                        }
+
+                        continue;
+                    }
+
+                    keys.insert(Self::associate_files_to_routes(
+                        site_name,
+                        &clean_route,
+                        &file_path,
+                        handlebars_handle,
+                    )?);
+
+                    if config.clean_urls_redirect {
+                        REDIRECTS.insert(
+                            route_key(site_name, &route_path(&route_index)),
+                            clean_route.into(),
+                        );
                     }
                 }
             } else {
-                Self::associate_files_to_routes(route, path, handlebars_handle)?
+                keys.insert(Self::associate_files_to_routes(
+                    site_name,
+                    route,
+                    path,
+                    handlebars_handle,
+                )?);
+            }
+        }
+
+        // the fingerprinted and the minified static files, the rest are left to the static file service
+        for asset in assets::site_assets(site_name).values() {
+            for url in asset.routes() {
+                keys.insert(Self::associate_asset_to_route(
+                    site_name,
+                    url,
+                    &asset.path,
+                    handlebars_handle,
+                )?);
+            }
+        }
+
+        // the single-page applications, their unknown paths get one of the routes
+        let mut spa_fallbacks = Vec::with_capacity(site.spa_fallback.len());
+
+        for (prefix, route) in &site.spa_fallback {
+            let key = route_key(site_name, route);
+
+            if !ROUTEMAP.contains_key(&key) {
+                anyhow::bail!("Unknown route {:?} in `spa_fallback`", route);
             }
+
+            spa_fallbacks.push((prefix.trim_end_matches('/').to_owned(), key));
         }
 
+        // the most specific prefix wins
+        spa_fallbacks.sort_unstable_by_key(|(prefix, _)| std::cmp::Reverse(prefix.len()));
+        SPA_FALLBACKS.insert(site_name.into(), spa_fallbacks);
+
         // generate the error pages
-        Self::add_error_pages()?;
+        Self::add_error_pages(site_name, &site.r#static, config)?;
 
-        Ok(())
+        Ok(keys)
     }
 
     /// Add error pages to the route handle for easy access.
-    pub fn add_error_pages() -> anyhow::Result<()> {
-        let not_found_page = generate_not_found()?;
+    pub fn add_error_pages(
+        site_name: &str,
+        static_config: &Static,
+        config: &Config,
+    ) -> anyhow::Result<()> {
+        let mut keys = HashSet::new();
 
-        let route_handle = RouteHandle {
-            r#type: Type::Bytes,
-            response: not_found_page,
-        };
+        ERROR_PAGE_SETTINGS.insert(
+            site_name.into(),
+            ErrorPageSettings {
+                minify_html: config.minify_html,
+            },
+        );
+
+        for (status, error_page) in generate_error_pages(static_config)? {
+            let route_handle = RouteHandle {
+                r#type: Type::Bytes,
+                response: error_page,
+            };
+
+            let key = error_page_key(site_name, status);
+            let size = route_handle.response.memory_size();
 
-        ROUTEMAP.insert("{{404}}".into(), route_handle);
+            ROUTEMAP.insert(key.to_owned(), route_handle);
+
+            // they're always in memory
+            CACHE.pin(&key, size);
+
+            keys.insert(key);
+        }
+
+        // forget the error pages which were removed from the configuration
+        let removed_keys: Vec<CompactString> = ROUTEMAP
+            .iter()
+            .map(|route| route.key().to_owned())
+            .filter(|key| {
+                let (site, route) = split_route_key(key);
+                site == site_name && route.starts_with("{{") && !keys.contains(key)
+            })
+            .collect();
+
+        for key in removed_keys {
+            ROUTEMAP.remove(&key);
+            CACHE.remove(&key);
+        }
 
         Ok(())
     }
 
-    /// Create route handlers for each specific file at initialization.
+    /// Create route handlers for each specific file at initialization, returns the key of the route.
     pub fn associate_files_to_routes(
-        route: &String,
+        site_name: &str,
+        route: &str,
         path: &PathBuf,
         handlebars_handle: &(handlebars::Handlebars, handlebars::Context),
-    ) -> anyhow::Result<()> {
+    ) -> anyhow::Result<CompactString> {
         // create a static file instance containing it's mime type, contents, and metadata
         let static_file = StaticFile::create(path, handlebars_handle)?;
 
+        Ok(Self::insert_route(site_name, route, static_file))
+    }
+
+    /// Create the route of a fingerprinted or minified static file, returns the key of the route.
+    ///
+    /// They're served from disk like the rest of the static files, so a fingerprinted `static`
+    /// directory isn't loaded into memory as a whole. (with `cache.max_memory` the most
+    /// requested ones are cached all the same)
+    pub fn associate_asset_to_route(
+        site_name: &str,
+        route: &str,
+        path: &PathBuf,
+        handlebars_handle: &(handlebars::Handlebars, handlebars::Context),
+    ) -> anyhow::Result<CompactString> {
+        let static_file = StaticFile::create_on_disk(path, handlebars_handle)?;
+
+        Ok(Self::insert_route(site_name, route, static_file))
+    }
+
+    /// Insert the route of a file into the routemap, returns the key of the route.
+    fn insert_route(site_name: &str, route: &str, static_file: StaticFile) -> CompactString {
         let route_handle = if static_file.bytes.is_empty() {
-            // this means the file is not in-memory
+            // this means the file is not in-memory (it's larger than `cache.max_file_size`,
+            // excluded by the `cache` globs or `fast_mem_cache` is disabled)
             RouteHandle {
                 r#type: Type::File,
                 response: static_file,
@@ -134,20 +592,24 @@ impl RouteHandle {
             }
         };
 
-        // pop the initial trailing slash if it exists
-        let mut route_fmt = route.chars();
-        let mut route_str: String = route.into();
+        let key = route_key(site_name, &route_path(route));
 
-        // this is to normalize multiple slashes
-        while route_str.starts_with('/') {
-            route_fmt.next();
-            route_str = route_fmt.as_str().into();
-        }
+        // the rendered templates and the minified files are always in memory
+        let pinned_size = route_handle
+            .response
+            .built_bytes()
+            .is_some()
+            .then(|| route_handle.response.memory_size());
 
-        route_str = format!("/{}", route_str);
+        ROUTEMAP.insert(key.to_owned(), route_handle);
 
-        ROUTEMAP.insert(route_str.into(), route_handle);
+        // it's no longer the cached version of the route
+        CACHE.forget(&key);
 
-        Ok(())
+        if let Some(size) = pinned_size {
+            CACHE.pin(&key, size);
+        }
+
+        key
     }
 }
@@ -1,73 +1,462 @@
 use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::HttpServiceFactory,
+    dev::{ServiceRequest, ServiceResponse},
+    error::InternalError,
+    guard,
     http::{
         header::{
-            HeaderMap, HeaderValue, CACHE_CONTROL, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH,
-            LAST_MODIFIED, SERVER,
+            HeaderValue, ACCEPT_RANGES, ALLOW, CACHE_CONTROL, CONTENT_ENCODING, CONTENT_LENGTH,
+            CONTENT_TYPE, ETAG, LAST_MODIFIED, LOCATION, SERVER, VARY, X_CONTENT_TYPE_OPTIONS,
         },
-        KeepAlive,
+        KeepAlive, Method, StatusCode,
     },
     middleware::{self, Compress, Condition, Logger},
-    web, App, HttpRequest, HttpResponse, HttpServer, Result,
+    web::{self, Bytes},
+    App, HttpRequest, HttpResponse, HttpResponseBuilder, HttpServer, Result,
 };
 
-use actix_files::{self, NamedFile};
-use actix_web_lab::middleware::RedirectHttps;
+use actix_files::{self, NamedFile, PathBufWrap};
+use actix_web_lab::middleware::{from_fn, Next};
 
+use compact_str::CompactString;
+
+use std::fs;
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
 use super::{
-    config::{BinserveConfig, CONFIG_STATE},
-    routes::{Type, ROUTEMAP},
-    tls,
+    acme,
+    cache::CACHE,
+    config::{BinserveConfig, CacheControlRule, Config, Site, CONFIG_STATE},
+    files::{self, Variant},
+    preconditions::{self, Precondition},
+    ranges::{self, Ranges},
+    routes::{self, ErrorPageSettings, Type, ROUTEMAP},
+    templates, tls,
 };
 
 use crate::cli::messages::{push_message, Type as MsgType};
 
-/// Check for `If-None-Matched` and `If-Modified-Since` headers
-/// for enabling browser caching.
+/// Methods allowed on the routes and the static files
+const ALLOWED_METHODS: &str = "GET, HEAD, OPTIONS";
+
+/// Content type of the representation of a `304 Not Modified` response
+struct RepresentationType(HeaderValue);
+
+/// Set the `Cache-Control` header of the first `cache_control` rule matching the response.
 ///
-/// All patterns are exhaustively checked to correctly handle contradicting values.
-async fn request_client_is_cached(
-    headers: &HeaderMap,
+/// Only the successful (and `304 Not Modified`) responses are cached by the rules, the rest
+/// and the responses not matching any of the rules are left to `enable_cache_control`.
+async fn cache_control<B: MessageBody>(
+    req: ServiceRequest,
+    next: Next<B>,
+    rules: Rc<[CacheControlRule]>,
+) -> Result<ServiceResponse<B>> {
+    // the path as the routes and the static file service resolve it
+    let path = routes::normalize_path(req.path());
+
+    let mut res = next.call(req).await?;
+
+    // an error (or a redirect) isn't the file the rule is about
+    let status = res.status();
+
+    if !status.is_success() && status != StatusCode::NOT_MODIFIED {
+        return Ok(res);
+    }
+
+    // the type of the served file, `304 Not Modified` responses without one
+    // are matched by the type of the requested path (as the static file service has it)
+    let mime_type = res
+        .headers()
+        .get(CONTENT_TYPE)
+        .cloned()
+        .or_else(|| {
+            res.response()
+                .extensions()
+                .get::<RepresentationType>()
+                .map(|mime_type| mime_type.0.to_owned())
+        })
+        .or_else(|| {
+            new_mime_guess::from_path(&path)
+                .first_raw()
+                .map(HeaderValue::from_static)
+        });
+
+    // without the parameters, like `; charset=utf-8`
+    let mime_type = mime_type
+        .as_ref()
+        .and_then(|mime_type| mime_type.to_str().ok())
+        .and_then(|mime_type| mime_type.split(';').next())
+        .map(str::trim);
+
+    if let Some(rule) = rules.iter().find(|rule| rule.matches(&path, mime_type)) {
+        if let Ok(value) = HeaderValue::from_str(&rule.value) {
+            res.headers_mut().insert(CACHE_CONTROL, value);
+        }
+    }
+
+    Ok(res)
+}
+
+/// Redirect plain HTTP requests to HTTPS.
+///
+/// ACME HTTP-01 challenges are let through as they are validated over plain HTTP.
+async fn redirect_https<B: MessageBody>(
+    req: ServiceRequest,
+    next: Next<B>,
+    tls_port: Option<u16>,
+) -> Result<ServiceResponse<EitherBody<B>>> {
+    let conn_info = req.connection_info().clone();
+
+    if conn_info.scheme() == "https" || req.path().starts_with(acme::CHALLENGE_PREFIX) {
+        return Ok(next.call(req).await?.map_into_left_body());
+    }
+
+    // construct equivalent https path, along with the query string
+    let host = conn_info.host();
+    let (hostname, _port) = host.split_once(':').unwrap_or((host, ""));
+    let path_and_query = req
+        .uri()
+        .path_and_query()
+        .map_or_else(|| req.path(), |path_and_query| path_and_query.as_str());
+    let location = match tls_port {
+        Some(port) => format!("https://{}:{}{}", hostname, port, path_and_query),
+        None => format!("https://{}{}", hostname, path_and_query),
+    };
+
+    Ok(req
+        .into_response(
+            HttpResponse::TemporaryRedirect()
+                .insert_header((LOCATION, location))
+                .finish(),
+        )
+        .map_into_right_body())
+}
+
+/// Refuse requests to the routes requiring a client certificate without a verified one.
+async fn require_client_certificate<B: MessageBody>(
+    req: ServiceRequest,
+    next: Next<B>,
+    routes: Rc<[String]>,
+) -> Result<ServiceResponse<EitherBody<B>>> {
+    if tls::requires_client_certificate(&routes, req.path())
+        && req.conn_data::<tls::ClientCertificate>().is_none()
+    {
+        return Ok(req
+            .into_response(HttpResponse::Forbidden().finish())
+            .map_into_right_body());
+    }
+
+    Ok(next.call(req).await?.map_into_left_body())
+}
+
+/// Mark a response as negotiated by `Accept-Encoding` and set the encoding of the served variant.
+fn encoding_headers(
+    response: &mut HttpResponseBuilder,
+    variants: &[Variant],
+    variant: Option<&Variant>,
+) {
+    if !variants.is_empty() {
+        response.insert_header((VARY, "accept-encoding"));
+    }
+
+    if let Some(variant) = variant {
+        response.insert_header((CONTENT_ENCODING, variant.encoding.as_str()));
+    }
+}
+
+/// Respond with `304 Not Modified` or `412 Precondition Failed` if the preconditions of the request say so.
+fn precondition_response(
+    req: &HttpRequest,
+    mime_type: &HeaderValue,
     etag: &HeaderValue,
     last_modified: &HeaderValue,
-) -> bool {
-    match (headers.get(IF_NONE_MATCH), headers.get(IF_MODIFIED_SINCE)) {
-        (None, None) => return false,
-        (Some(req_etag), Some(req_last_modified)) => {
-            // if both of them match the requested file's state,
-            // return `304` to respond with the browser cache
-            if req_etag == etag && req_last_modified == last_modified {
-                return true;
-            }
+    variants: &[Variant],
+    variant: Option<&Variant>,
+) -> Option<HttpResponse> {
+    match preconditions::evaluate(req, etag, last_modified) {
+        Precondition::Passed => None,
+        // the client's cached representation is current
+        Precondition::NotModified => {
+            let mut response = HttpResponse::NotModified();
+            encoding_headers(&mut response, variants, variant);
+
+            // it has no `Content-Type`, but the `cache_control` rules still need it
+            response
+                .extensions_mut()
+                .insert(RepresentationType(mime_type.to_owned()));
+
+            Some(
+                response
+                    .insert_header((LAST_MODIFIED, last_modified))
+                    .insert_header((ETAG, etag))
+                    .finish(),
+            )
         }
-        (Some(req_etag), None) => {
-            // if the etag value match the requested file's state,
-            // return `304` to respond with the browser cache
-            if req_etag == etag {
-                return true;
-            }
+        Precondition::Failed => Some(HttpResponse::PreconditionFailed().finish()),
+    }
+}
+
+/// Respond with an in-memory body, or the byte ranges of it requested by the client.
+fn bytes_response(
+    req: &HttpRequest,
+    body: Bytes,
+    mime_type: &HeaderValue,
+    etag: &HeaderValue,
+    last_modified: &HeaderValue,
+    variants: &[Variant],
+    variant: Option<&Variant>,
+) -> HttpResponse {
+    if let Some(response) =
+        precondition_response(req, mime_type, etag, last_modified, variants, variant)
+    {
+        return response;
+    }
+
+    let mut response = HttpResponse::Ok();
+    encoding_headers(&mut response, variants, variant);
+
+    response
+        .insert_header((LAST_MODIFIED, last_modified))
+        .insert_header((ETAG, etag))
+        .insert_header((ACCEPT_RANGES, "bytes"));
+
+    match ranges::requested(req, body.len() as u64, etag, last_modified) {
+        // fallback to returning the current state by default
+        //
+        // NOTE: should we `stream` body here, testing didn't show much changes?
+        Ranges::Full => response.content_type(mime_type).body(body),
+        // the compression middleware doesn't encode the 206 responses
+        Ranges::Partial(ranges) => ranges::partial_content(response, body, mime_type, &ranges),
+        Ranges::Unsatisfiable => ranges::not_satisfiable(response, body.len() as u64),
+    }
+}
+
+/// Serve a file from disk, or its precompressed variant preferred by the client.
+///
+/// The validators are the ones of the route, so they don't change as it's cached or evicted.
+fn file_response(
+    req: &HttpRequest,
+    path: &Path,
+    etag: &HeaderValue,
+    last_modified: &HeaderValue,
+    variants: &[Variant],
+) -> Result<HttpResponse> {
+    let variant = files::preferred_variant(variants, req);
+    let etag = variant.map_or(etag, |variant| &variant.etag);
+
+    let file = match variant {
+        Some(variant) => NamedFile::open(&variant.path)?
+            // keep the type of the original file instead of the `.br`/`.zst`/`.gz` one
+            .set_content_type(new_mime_guess::from_path(path).first_or_octet_stream())
+            .disable_content_disposition()
+            .set_content_encoding(variant.encoding),
+        None => NamedFile::open(path)?,
+    };
+
+    let mime_type = HeaderValue::from_str(file.content_type().essence_str())?;
+
+    if let Some(response) =
+        precondition_response(req, &mime_type, etag, last_modified, variants, variant)
+    {
+        return Ok(response);
+    }
+
+    let mut response = file
+        .prefer_utf8(true)
+        .use_etag(false)
+        .use_last_modified(false)
+        .into_response(req);
+
+    let headers = response.headers_mut();
+    headers.insert(ETAG, etag.to_owned());
+    headers.insert(LAST_MODIFIED, last_modified.to_owned());
+
+    if !variants.is_empty() {
+        headers.insert(VARY, HeaderValue::from_static("accept-encoding"));
+    }
+
+    Ok(response)
+}
+
+/// Serve the files of the `static` directory which are routes (fingerprinted or minified)
+/// or have precompressed sidecars, the rest are left to the static file service.
+async fn static_variants<B: MessageBody>(
+    req: ServiceRequest,
+    next: Next<B>,
+    served_from: Rc<str>,
+    directory: Rc<Path>,
+    follow_symlinks: bool,
+) -> Result<ServiceResponse<EitherBody<B>>> {
+    // the fingerprinted and the minified static files are routes
+    let site = routes::resolve_site(routes::request_host(req.head()));
+
+    if ROUTEMAP.contains_key(&routes::route_key(&site, req.path())) {
+        let response = router(req.request().to_owned()).await?;
+        return Ok(req.into_response(response).map_into_right_body());
+    }
+
+    let path = req
+        .path()
+        .strip_prefix(&*served_from)
+        .filter(|path| path.is_empty() || path.starts_with('/'))
+        .and_then(|path| PathBufWrap::parse_path(path, false).ok())
+        .map(|path| directory.join(path))
+        // don't follow symlinks unless explicitly stated otherwise
+        .filter(|path| {
+            path.symlink_metadata()
+                .is_ok_and(|m| m.is_file() || (follow_symlinks && path.is_file()))
+        });
+
+    if let Some(path) = path {
+        if let Some(response) = method_response(req.request()) {
+            return Ok(req.into_response(response).map_into_right_body());
         }
-        (None, Some(req_last_modified)) => {
-            // if the last modified system time match the requested file's state,
-            // return `304` to respond with the browser cache
-            if req_last_modified == last_modified {
-                return true;
-            }
+
+        let variants = files::find_variants(&path, follow_symlinks).unwrap_or_default();
+
+        if !variants.is_empty() {
+            let (etag, last_modified) = files::validators(&fs::metadata(&path)?)?;
+            let response = file_response(req.request(), &path, &etag, &last_modified, &variants)?;
+            return Ok(req.into_response(response).map_into_right_body());
         }
     }
 
-    false
+    Ok(next.call(req).await?.map_into_left_body())
+}
+
+/// Respond with the error page of the site for the status code of the error responses,
+/// of the routes, the static file service and binserve itself alike.
+async fn error_pages<B: MessageBody + 'static>(
+    req: ServiceRequest,
+    next: Next<B>,
+) -> Result<ServiceResponse<EitherBody<B>>> {
+    let site = routes::resolve_site(routes::request_host(req.head()));
+    let path = req.path().to_owned();
+
+    let res = match next.call(req).await {
+        Ok(res) => res,
+        // errors which haven't been turned into a response yet
+        Err(e) => {
+            let response = with_error_page(&site, &path, e.error_response());
+            return Err(InternalError::from_response(e, response).into());
+        }
+    };
+
+    let status = res.status();
+
+    if !(status.is_client_error() || status.is_server_error()) {
+        return Ok(res.map_into_left_body());
+    }
+
+    let (request, response) = res.into_parts();
+    let response = with_error_page(&site, &path, response.map_into_boxed_body());
+
+    Ok(ServiceResponse::new(request, response).map_into_right_body())
+}
+
+/// The error page settings of a site, as of the last build.
+fn error_page_settings(site: &str) -> ErrorPageSettings {
+    routes::ERROR_PAGE_SETTINGS
+        .get(site)
+        .map(|settings| *settings)
+        .unwrap_or_default()
+}
+
+/// Replace the body of an error response with the error page for it's status code, if any.
+fn with_error_page(site: &str, path: &str, response: HttpResponse) -> HttpResponse {
+    let status = response.status();
+
+    let Some(error_page) = ROUTEMAP.get(&routes::error_page_key(site, status)) else {
+        return response;
+    };
+
+    let page = &error_page.response;
+
+    // not loaded, like a symlink when `follow_symlinks` is disabled
+    let Some(mime_type) = page.mime.to_owned() else {
+        return response;
+    };
+
+    let body = match files::is_template(&page.path) {
+        true => match templates::render_error_page(site, &page.bytes, status, path) {
+            Ok(rendered) if error_page_settings(site).minify_html => {
+                files::minify_html(Bytes::from(rendered))
+            }
+            Ok(rendered) => Bytes::from(rendered),
+            Err(e) => {
+                push_message(
+                    MsgType::Error,
+                    &format!(
+                        "Failed to render the error page {:?}: {:#}",
+                        page.path.to_string_lossy(),
+                        e
+                    ),
+                );
+
+                return response;
+            }
+        },
+        false => page.bytes.to_owned(),
+    };
+
+    drop(error_page);
+
+    let mut response = response.set_body(body);
+
+    // the page is a different representation than the one of the response (if any)
+    let headers = response.headers_mut();
+    headers.insert(CONTENT_TYPE, mime_type);
+    headers.insert(X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+    headers.remove(CONTENT_ENCODING);
+    headers.remove(CONTENT_LENGTH);
+
+    response.map_into_boxed_body()
+}
+
+/// Answer the methods other than `GET` and `HEAD` on a route or a static file, `OPTIONS` with
+/// the allowed methods and the rest with `405 Method Not Allowed`.
+///
+/// The body of the `HEAD` responses is left out by actix-web, the headers are the same as `GET`.
+fn method_response(req: &HttpRequest) -> Option<HttpResponse> {
+    let mut response = match *req.method() {
+        Method::GET | Method::HEAD => return None,
+        Method::OPTIONS => HttpResponse::NoContent(),
+        _ => HttpResponse::MethodNotAllowed(),
+    };
+
+    Some(response.insert_header((ALLOW, ALLOWED_METHODS)).finish())
 }
 
 /// Route matcher and handles all HTTP requests. (registered as the `default_service`)
 async fn router(req: HttpRequest) -> Result<HttpResponse> {
-    match ROUTEMAP.get(req.path()) {
+    // answer ACME HTTP-01 challenges for automatic HTTPS
+    if let Some(key_authorization) = acme::challenge_response(req.path()) {
+        return Ok(HttpResponse::Ok()
+            .content_type("application/octet-stream")
+            .body(key_authorization.to_string()));
+    }
+
+    // routes of the site matching the requested host
+    let site = routes::resolve_site(routes::request_host(req.head()));
+
+    let key = routes::route_key(&site, req.path());
+
+    // the unknown paths of a single-page application get its page
+    let key = match ROUTEMAP.contains_key(&key) {
+        true => key,
+        false => routes::spa_fallback(&site, req.path()).unwrap_or(key),
+    };
+
+    match ROUTEMAP.get(&key) {
         Some(handler) => {
+            if let Some(response) = method_response(&req) {
+                return Ok(response);
+            }
+
             let handler = handler.value();
 
-            // the response body in `Bytes`
-            let body = handler.response.bytes.to_owned();
             // the mime type (`Content-Type`) derived from the file
             let mime_type = handler.response.mime.as_ref().unwrap();
             // the etag derived from the file metadata
@@ -79,67 +468,180 @@ async fn router(req: HttpRequest) -> Result<HttpResponse> {
             if handler.r#type == Type::File {
                 let path = &handler.response.path;
 
-                // handlebars templates are always pre-rendered at initialization and stored in-memory
-                if path.to_string_lossy().ends_with(".hbs") {
-                    // get the request headers
-                    let headers = req.headers();
-
-                    // if the request client is cached, respond with the cache content (304 Not Modified)
-                    if request_client_is_cached(headers, etag, last_modified).await {
-                        // 304 Not Modified
-                        return Ok(HttpResponse::NotModified()
-                            .insert_header((LAST_MODIFIED, last_modified))
-                            .insert_header((ETAG, etag))
-                            .finish());
-                    }
-
-                    return Ok(HttpResponse::Ok()
-                        .insert_header((LAST_MODIFIED, last_modified))
-                        .insert_header((ETAG, etag))
-                        .content_type(mime_type)
-                        .body(handler.response.hbs_bytes.to_owned()));
+                // handlebars templates are always pre-rendered at initialization and stored in-memory,
+                // and so are the minified files
+                if let Some(body) = handler.response.built_bytes() {
+                    CACHE.hit(&key);
+
+                    let body = body.to_owned();
+
+                    return Ok(bytes_response(
+                        &req,
+                        body,
+                        mime_type,
+                        etag,
+                        last_modified,
+                        &[],
+                        None,
+                    ));
                 }
 
-                return Ok(NamedFile::open(&handler.response.path)?
+                CACHE.miss(&key);
+
+                return file_response(&req, path, etag, last_modified, &handler.response.variants);
+            }
+
+            CACHE.hit(&key);
+
+            // serve the precompressed variant preferred by the client, if any
+            let variants = &handler.response.variants;
+            let variant = files::preferred_variant(variants, &req);
+
+            let (body, etag) = match variant {
+                Some(variant) => (variant.bytes.to_owned(), &variant.etag),
+                None => (handler.response.bytes.to_owned(), etag),
+            };
+
+            Ok(bytes_response(
+                &req,
+                body,
+                mime_type,
+                etag,
+                last_modified,
+                variants,
+                variant,
+            ))
+        }
+        None => {
+            // the `.html` form of a clean URL (`clean_urls_redirect`)
+            if let Some(clean_route) = routes::REDIRECTS.get(&key) {
+                let location = match req.query_string() {
+                    "" => clean_route.to_string(),
+                    query => format!("{}?{}", clean_route.as_str(), query),
+                };
+
+                return Ok(HttpResponse::MovedPermanently()
+                    .insert_header((LOCATION, location))
+                    .finish());
+            }
+
+            // the body is the error page of the site (see `error_pages`)
+            Ok(HttpResponse::NotFound().finish())
+        }
+    }
+}
+
+/// Statistics of the in-memory cache. (`cache.stats_route`)
+async fn cache_stats() -> HttpResponse {
+    HttpResponse::Ok().json(CACHE.stats())
+}
+
+/// Create the service of a site with it's static files and routes.
+fn site_service(site_name: CompactString, site: &Site, config: &Config) -> impl HttpServiceFactory {
+    let mut scope = web::scope("");
+
+    // virtual hosts only handle the requests for their own host
+    if !site_name.is_empty() {
+        scope = scope.guard(guard::fn_guard(move |ctx| {
+            routes::resolve_site(routes::request_host(ctx.head())) == site_name
+        }));
+    }
+
+    let static_served_from = &site.r#static.served_from;
+    let static_directory = &site.r#static.directory;
+
+    let has_static_directory =
+        !static_served_from.is_empty() && static_directory != &PathBuf::new();
+
+    if has_static_directory {
+        scope = scope.service({
+            let directory = static_directory.to_owned();
+
+            let mut static_file_service =
+                actix_files::Files::new(static_served_from, static_directory)
+                    // don't follow symlinks unless explicitly stated otherwise
+                    .path_filter(move |path, _| {
+                        let config_state = &*CONFIG_STATE.lock();
+
+                        // if configured to follow symlinks
+                        if config_state.config.follow_symlinks {
+                            false
+                        } else {
+                            directory
+                                .join(path)
+                                .symlink_metadata()
+                                .map(|m| !m.file_type().is_symlink())
+                                .unwrap_or(false)
+                        }
+                    })
                     .prefer_utf8(true)
                     .use_etag(true)
                     .use_last_modified(true)
-                    .into_response(&req));
+                    // the missing files get the error pages
+                    .default_handler(web::to(router));
+
+            // if configured to allow directory listing or not
+            // for the static files.
+            if config.enable_directory_listing {
+                static_file_service = static_file_service.show_files_listing()
             }
 
-            // get the request headers
-            let headers = req.headers();
+            static_file_service
+        });
+    }
 
-            // if the request client is cached, respond with the cache content (304 Not Modified)
-            if request_client_is_cached(headers, etag, last_modified).await {
-                // 304 Not Modified
-                return Ok(HttpResponse::NotModified()
-                    .insert_header((LAST_MODIFIED, last_modified))
-                    .insert_header((ETAG, etag))
-                    .finish());
+    let served_from: Rc<str> = static_served_from.trim_end_matches('/').into();
+    let directory: Rc<Path> = static_directory.as_path().into();
+    let follow_symlinks = config.follow_symlinks;
+
+    scope
+        // routes and precompressed sidecars of the static files
+        .wrap(Condition::new(
+            has_static_directory,
+            from_fn(move |req, next| {
+                static_variants(
+                    req,
+                    next,
+                    served_from.clone(),
+                    directory.clone(),
+                    follow_symlinks,
+                )
+            }),
+        ))
+        .wrap({
+            let mut headers_middleware = middleware::DefaultHeaders::new();
+
+            // overwrite specified headers
+            for (header, value) in site.insert_headers.iter() {
+                headers_middleware = headers_middleware.add((header.as_str(), value.as_str()));
             }
 
-            // fallback to returning the current state by default
-            Ok(HttpResponse::Ok()
-                .insert_header((LAST_MODIFIED, last_modified))
-                .insert_header((ETAG, etag))
-                .content_type(mime_type)
-                .body(body)) // NOTE: should we `stream` body here, testing didn't show much changes?
-        }
-        None => {
-            let not_found_handler = ROUTEMAP.get("{{404}}").unwrap();
-            let handle = not_found_handler.value();
-            let not_found_response = &handle.response.bytes;
-            Ok(HttpResponse::NotFound().body(not_found_response.to_owned()))
-        }
-    }
+            headers_middleware
+        })
+        .default_service(web::to(router))
 }
 
 /// Run the actix-web server.
 #[actix_web::main]
 pub async fn run_server(config_state: BinserveConfig) -> std::io::Result<()> {
+    let default_site = config_state.default_site();
+
     let mut http_server = HttpServer::new(move || {
+        let client_auth = &config_state.server.tls.client_auth;
+        let client_auth = config_state.server.tls.enable && client_auth.enable;
+
         let mut app_instance = App::new()
+            // only let verified client certificates through to the protected routes
+            .wrap({
+                let routes: Rc<[String]> = config_state.server.tls.client_auth.routes.clone().into();
+
+                Condition::new(
+                    client_auth && !routes.is_empty(),
+                    from_fn(move |req, next| require_client_certificate(req, next, routes.clone())),
+                )
+            })
+            // the error pages of the sites, for the error responses of all of the services
+            .wrap(from_fn(error_pages))
             .wrap({
                 // by default env has to be initialized to log events
                 let mut logger = Logger::new("");
@@ -149,11 +651,33 @@ pub async fn run_server(config_state: BinserveConfig) -> std::io::Result<()> {
                     env_logger::try_init_from_env(env_logger::Env::new().default_filter_or("info"))
                         .unwrap_or_default();
 
-                    logger = Logger::new("%a \"%r\" %s %b \"%{Referer}i\" \"%{User-Agent}i\" %T");
+                    if client_auth {
+                        // log the subject of the verified client certificate
+                        logger = Logger::new(
+                            "%a \"%r\" %s %b \"%{Referer}i\" \"%{User-Agent}i\" \"%{client_subject}xi\" %T",
+                        )
+                        .custom_request_replace("client_subject", |req| {
+                            req.conn_data::<tls::ClientCertificate>()
+                                .map(|cert| cert.subject.to_owned())
+                                .unwrap_or_else(|| "-".into())
+                        });
+                    } else {
+                        logger =
+                            Logger::new("%a \"%r\" %s %b \"%{Referer}i\" \"%{User-Agent}i\" %T");
+                    }
                 }
 
                 logger
             })
+            // `Cache-Control` policies of the paths and MIME types
+            .wrap({
+                let rules: Rc<[CacheControlRule]> = config_state.cache_control.clone().into();
+
+                Condition::new(
+                    !rules.is_empty(),
+                    from_fn(move |req, next| cache_control(req, next, rules.clone())),
+                )
+            })
             .wrap({
                 let mut headers_middleware = middleware::DefaultHeaders::new();
 
@@ -169,23 +693,11 @@ pub async fn run_server(config_state: BinserveConfig) -> std::io::Result<()> {
                     headers_middleware = headers_middleware.add((CACHE_CONTROL, "no-cache"));
                 }
 
-                // overwrite specified headers
-                if !config_state.insert_headers.is_empty() {
-                    for (header, value) in config_state.insert_headers.iter() {
-                        headers_middleware =
-                            headers_middleware.add((header.as_str(), value.as_str()));
-                    }
-                }
-
                 headers_middleware
             })
             .wrap(Compress::default())
             // enable TLS autoredirect to HTTPs
             .wrap({
-                // enable default redirect
-                let mut middleware =
-                    Condition::new(config_state.server.tls.enable, RedirectHttps::default());
-
                 // if it's a port like 8443, resolve to that instead
                 let tls_host = &config_state.server.tls.host;
 
@@ -194,58 +706,39 @@ pub async fn run_server(config_state: BinserveConfig) -> std::io::Result<()> {
                 // since lookups won't complete in debug stages anyway
                 let tls_address = tls_host.split(':');
                 let tls_address = tls_address.collect::<Vec<&str>>();
-                let mut tls_port = "443";
-                if tls_address.len() > 1 {
-                    tls_port = tls_address[1]
-                }
-
-                if tls_port != "443" {
-                    middleware = Condition::new(
-                        config_state.server.tls.enable,
-                        RedirectHttps::default().to_port(tls_port.parse::<u16>().unwrap()),
-                    );
+                let mut tls_port = None;
+                if tls_address.len() > 1 && tls_address[1] != "443" {
+                    tls_port = Some(tls_address[1].parse::<u16>().unwrap())
                 }
 
-                middleware
+                Condition::new(
+                    config_state.server.tls.enable,
+                    from_fn(move |req, next| redirect_https(req, next, tls_port)),
+                )
             });
 
-        let static_served_from = &config_state.r#static.served_from;
-        let static_directory = &config_state.r#static.directory;
-
-        if !static_served_from.is_empty() && static_directory != &PathBuf::new() {
-            app_instance = app_instance.service({
-                let mut static_file_service =
-                    actix_files::Files::new(static_served_from, static_directory)
-                        // don't follow symlinks unless explicitly stated otherwise
-                        .path_filter(|path, _| {
-                            let config_state = &*CONFIG_STATE.lock();
-
-                            // if configured to follow symlinks
-                            if config_state.config.follow_symlinks {
-                                false
-                            } else {
-                                Path::new(&config_state.r#static.directory)
-                                    .join(path)
-                                    .symlink_metadata()
-                                    .map(|m| !m.file_type().is_symlink())
-                                    .unwrap_or(false)
-                            }
-                        })
-                        .prefer_utf8(true)
-                        .use_etag(true)
-                        .use_last_modified(true);
-
-                // if configured to allow directory listing or not
-                // for the static files.
-                if config_state.config.enable_directory_listing {
-                    static_file_service = static_file_service.show_files_listing()
-                }
+        // expose the cache statistics if configured
+        let stats_route = &config_state.cache.stats_route;
 
-                static_file_service
-            });
+        if !stats_route.is_empty() {
+            app_instance = app_instance.route(stats_route, web::get().to(cache_stats));
+        }
+
+        // virtual hosts, matched by the `Host` header
+        for (site_name, site) in config_state.sites.iter() {
+            app_instance = app_instance.service(site_service(
+                site_name.into(),
+                site,
+                &config_state.config,
+            ));
         }
 
-        app_instance.default_service(web::get().to(router))
+        // the default site catches all of the other hosts
+        app_instance.service(site_service(
+            CompactString::default(),
+            &default_site,
+            &config_state.config,
+        ))
     })
     .bind({
         // port doesn't have to be explicitly specified
@@ -265,18 +758,89 @@ pub async fn run_server(config_state: BinserveConfig) -> std::io::Result<()> {
         host
     })?
     .max_connection_rate(500)
-    .keep_alive(KeepAlive::Os);
+    .keep_alive(KeepAlive::Os)
+    // expose the verified client certificates to the requests
+    .on_connect(tls::on_connect);
 
     // enable TLS connection
     let config_state = BinserveConfig::read()?;
 
     if config_state.server.tls.enable {
         let tls_host = &config_state.server.tls.host;
-        let tls_config = tls::load_rustls_config().unwrap();
+        let tls_config =
+            tls::load_rustls_config().map_err(|e| std::io::Error::other(format!("{:#}", e)))?;
 
         // bind the TLS host and the rustls configuration
         http_server = http_server.bind_rustls_0_22(tls_host, tls_config)?;
+
+        // issue and renew certificates in the background
+        if config_state.server.tls.acme.enable {
+            actix_web::rt::spawn(acme::renew_certificates());
+        }
     }
 
     http_server.run().await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use actix_web::{body, test::TestRequest};
+
+    use crate::core::assets;
+    use routes::RouteHandle;
+
+    use std::fs;
+
+    async fn get(uri: &str) -> HttpResponse {
+        router(TestRequest::get().uri(uri).to_http_request())
+            .await
+            .unwrap()
+    }
+
+    async fn body_of(uri: &str) -> Bytes {
+        body::to_bytes(get(uri).await.into_body()).await.unwrap()
+    }
+
+    #[actix_web::test]
+    async fn serves_the_clean_urls_and_redirects_the_html_ones() {
+        let directory = std::env::temp_dir().join(format!("binserve-{}-clean", std::process::id()));
+        fs::create_dir_all(directory.join("about")).unwrap();
+        fs::write(directory.join("docs.html"), "docs").unwrap();
+        fs::write(directory.join("about.html"), "about.html").unwrap();
+        fs::write(directory.join("about/index.html"), "about/index.html").unwrap();
+
+        let site = Site {
+            routes: [
+                ("/".to_string(), directory.to_owned()),
+                ("/nested".to_string(), directory.to_owned()),
+            ]
+            .into(),
+            ..Site::default()
+        };
+        let config = Config {
+            clean_urls: true,
+            clean_urls_redirect: true,
+            ..Config::default()
+        };
+
+        let handlebars_handle =
+            templates::render_templates(&site.template, assets::site_assets("")).unwrap();
+        RouteHandle::add_routes("", &site, &config, &handlebars_handle).unwrap();
+
+        assert_eq!(body_of("/docs").await, "docs");
+        assert_eq!(body_of("/nested/docs").await, "docs");
+
+        // the query is kept along
+        let response = get("/docs.html?lang=en").await;
+        assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(response.headers().get(LOCATION).unwrap(), "/docs?lang=en");
+
+        // the index file wins whatever the walk order is, `about.html` is served as is
+        assert_eq!(body_of("/about").await, "about/index.html");
+        assert_eq!(body_of("/about.html").await, "about.html");
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
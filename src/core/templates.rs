@@ -1,18 +1,134 @@
-use handlebars::{Context as HbsContext, Handlebars};
+use handlebars::{
+    Context as HbsContext, Handlebars, Helper, HelperDef, HelperResult, Output, RenderContext,
+    RenderErrorReason,
+};
+
+use actix_web::http::StatusCode;
+
+use ahash::RandomState;
+use compact_str::CompactString;
+use dashmap::DashMap;
+use once_cell::sync::Lazy;
+
+use std::sync::Arc;
 
 use anyhow::{Context, Result};
 
-use super::config::BinserveConfig;
+use super::assets::{self, Asset, Assets};
+use super::config::Template;
+
+/// `{{asset "css/styles.css"}}` resolves to the URL of a file in the `static` directory,
+/// the fingerprinted one if `static.fingerprint` is enabled.
+struct AssetHelper(Arc<Assets>);
+
+impl HelperDef for AssetHelper {
+    fn call<'reg: 'rc, 'rc>(
+        &self,
+        helper: &Helper<'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc HbsContext,
+        _: &mut RenderContext<'reg, 'rc>,
+        out: &mut dyn Output,
+    ) -> HelperResult {
+        let asset = find_asset(&self.0, helper, "asset")?;
+
+        out.write(&asset.url)?;
+
+        Ok(())
+    }
+}
+
+/// `{{sri "js/app.js"}}` resolves to the Subresource Integrity metadata of a file
+/// in the `static` directory (e.g. `sha384-...`), it's hashed once per build of the assets.
+struct SriHelper(Arc<Assets>);
+
+impl HelperDef for SriHelper {
+    fn call<'reg: 'rc, 'rc>(
+        &self,
+        helper: &Helper<'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc HbsContext,
+        _: &mut RenderContext<'reg, 'rc>,
+        out: &mut dyn Output,
+    ) -> HelperResult {
+        let asset = find_asset(&self.0, helper, "sri")?;
+
+        let integrity = asset.integrity().map_err(|e| {
+            RenderErrorReason::Other(format!("Failed to hash {:?}: {}", asset.path, e))
+        })?;
+
+        out.write(integrity)?;
+
+        Ok(())
+    }
+}
+
+/// The asset named by the first parameter of a helper, by it's path in the `static` directory or URL.
+fn find_asset<'a>(
+    assets: &'a Assets,
+    helper: &Helper,
+    helper_name: &'static str,
+) -> Result<&'a Asset, RenderErrorReason> {
+    let name = helper
+        .param(0)
+        .and_then(|param| param.value().as_str())
+        .ok_or(RenderErrorReason::ParamNotFoundForIndex(helper_name, 0))?;
+
+    // a typo shouldn't go unnoticed until the link breaks
+    assets::find(assets, name).ok_or_else(|| {
+        RenderErrorReason::Other(format!("No file {:?} in the static directory", name))
+    })
+}
+
+/// The handlebars registry and the template variables of a site
+pub type HandlebarsHandle = (Handlebars<'static>, HbsContext);
+
+/// The handlebars handle of each site, the error pages are rendered per request
+/// as their context includes the status code and the path of the request.
+pub static SITE_TEMPLATES: Lazy<DashMap<CompactString, HandlebarsHandle, RandomState>> =
+    Lazy::new(|| DashMap::with_hasher(RandomState::new()));
+
+/// Render an error page of a site with the template variables, the status code
+/// (`status_code` and `reason`) and the `path` of the request.
+pub fn render_error_page(
+    site_name: &str,
+    template: &[u8],
+    status: StatusCode,
+    path: &str,
+) -> Result<String> {
+    let handlebars_handle = SITE_TEMPLATES
+        .get(site_name)
+        .context("The templates of the site aren't prepared")?;
 
-/// Prepare the partials and template variables for handlebars at initialization.
-pub fn render_templates(config: &BinserveConfig) -> Result<(Handlebars<'static>, HbsContext)> {
+    let (hbs_reg, hbs_ctx) = &*handlebars_handle;
+
+    let mut data = hbs_ctx.data().to_owned();
+
+    if let Some(variables) = data.as_object_mut() {
+        variables.insert("status_code".into(), status.as_u16().into());
+        variables.insert(
+            "reason".into(),
+            status.canonical_reason().unwrap_or_default().into(),
+        );
+        variables.insert("path".into(), path.into());
+    }
+
+    Ok(hbs_reg.render_template(&String::from_utf8_lossy(template), &data)?)
+}
+
+/// Prepare the partials, template variables and helpers for handlebars at initialization.
+pub fn render_templates(template: &Template, assets: Arc<Assets>) -> Result<HandlebarsHandle> {
     let mut handlebars_reg = Handlebars::new();
 
+    // register the helpers
+    handlebars_reg.register_helper("asset", Box::new(AssetHelper(assets.clone())));
+    handlebars_reg.register_helper("sri", Box::new(SriHelper(assets)));
+
     // register the context with the template variables
-    let hbs_context = HbsContext::wraps(&config.template.variables)?;
+    let hbs_context = HbsContext::wraps(&template.variables)?;
 
     // prepare template partials
-    for (partial_name, template_path) in &config.template.partials {
+    for (partial_name, template_path) in &template.partials {
         // register the partial templates
         let partial_template = std::fs::read_to_string(template_path).with_context(|| {
             format!(
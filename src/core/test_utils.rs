@@ -0,0 +1,30 @@
+//! Fixtures shared by the tests of the TLS modules.
+
+use rcgen::{BasicConstraints, Certificate, CertificateParams, IsCa, KeyPair};
+
+use std::fs;
+use std::path::PathBuf;
+
+/// A private CA, like the one of Pebble.
+pub fn private_ca() -> (Certificate, KeyPair) {
+    let mut params = CertificateParams::default();
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+
+    let key_pair = KeyPair::generate().unwrap();
+    (params.self_signed(&key_pair).unwrap(), key_pair)
+}
+
+/// A file in the temporary directory, removed once the test is done with it.
+pub struct TempFile(pub PathBuf);
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+pub fn write_temp(name: &str, contents: impl AsRef<[u8]>) -> TempFile {
+    let path = std::env::temp_dir().join(format!("binserve-{}-{}", std::process::id(), name));
+    fs::write(&path, contents).unwrap();
+    TempFile(path)
+}
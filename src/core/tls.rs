@@ -1,45 +1,482 @@
-use rustls::ServerConfig;
-use rustls_pemfile::{certs, pkcs8_private_keys};
+use rustls::{
+    crypto::{ring, CryptoProvider},
+    pki_types::CertificateDer,
+    server::{danger::ClientCertVerifier, ClientHello, ResolvesServerCert, WebPkiClientVerifier},
+    sign::CertifiedKey,
+    ProtocolVersion, RootCertStore, ServerConfig, SupportedCipherSuite, SupportedProtocolVersion,
+};
+use rustls_pemfile::{certs, private_key};
 
-use std::fs::File;
-use std::io::BufReader;
+use actix_tls::accept::rustls_0_22::TlsStream;
+use actix_web::{dev::Extensions, rt::net::TcpStream};
 
-use anyhow::{Context, Result};
+use x509_parser::prelude::{FromDer, X509Certificate};
 
-use super::config::CONFIG_STATE;
+use std::any::Any;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+
+use compact_str::CompactString;
+
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+
+use super::config::{ClientAuth, ClientAuthMode, Ocsp, Tls, TlsVersion, CONFIG_STATE};
+use super::{ocsp, routes};
 
 use crate::cli::messages::{push_message, Type};
 
+/// Certificate resolver picking the certificate by SNI (Server Name Indication).
+///
+/// Certificates can be swapped at runtime, this is used for the ones which
+/// are not available (or change) after the server has started, like the ones
+/// issued by ACME.
+#[derive(Debug, Default)]
+pub struct CertResolver {
+    // served when no certificate matches the requested server name
+    default: RwLock<Option<Arc<CertifiedKey>>>,
+    // server name (or `*.` wildcard) -> certificate
+    server_names: RwLock<HashMap<CompactString, Arc<CertifiedKey>>>,
+}
+
+impl CertResolver {
+    /// Replace the default certificate served for new TLS handshakes.
+    pub fn set(&self, certified_key: CertifiedKey) {
+        *self.default.write() = Some(Arc::new(certified_key));
+    }
+
+    /// Replace the certificates served for specific server names.
+    pub fn set_server_names(&self, certified_keys: HashMap<CompactString, Arc<CertifiedKey>>) {
+        *self.server_names.write() = certified_keys;
+    }
+
+    /// The default certificate currently served.
+    pub fn default_certificate(&self) -> Option<Arc<CertifiedKey>> {
+        self.default.read().clone()
+    }
+
+    /// The certificate currently served for a server name.
+    pub fn server_name_certificate(&self, server_name: &str) -> Option<Arc<CertifiedKey>> {
+        self.server_names.read().get(server_name).cloned()
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        if let Some(server_name) = client_hello.server_name() {
+            let server_names = self.server_names.read();
+            let server_name = server_name.to_ascii_lowercase();
+
+            // exact matches take precedence over wildcards
+            if let Some(certified_key) = server_names.get(server_name.as_str()) {
+                return Some(certified_key.clone());
+            }
+
+            if let Some((_, parent)) = server_name.split_once('.') {
+                let wildcard = format!("*.{}", parent);
+
+                if let Some(certified_key) = server_names.get(wildcard.as_str()) {
+                    return Some(certified_key.clone());
+                }
+            }
+        }
+
+        // fallback to the default certificate for unknown server names
+        self.default.read().clone()
+    }
+}
+
+/// The resolver shared between the TLS listener and the ACME renewal task
+pub static CERT_RESOLVER: Lazy<Arc<CertResolver>> = Lazy::new(|| Arc::new(CertResolver::default()));
+
+/// Parse a PEM encoded certificate chain and private key into a signing certificate.
+///
+/// The private key can be in any of the formats `rustls_pemfile` reads:
+/// PKCS#8, PKCS#1 (RSA) or SEC1 (EC).
+pub fn certified_key_from_pem(cert_chain: &[u8], key: &[u8]) -> Result<CertifiedKey> {
+    let cert_chain = certs(&mut &cert_chain[..]).collect::<Result<Vec<_>, _>>()?;
+
+    if cert_chain.is_empty() {
+        bail!("Could not locate any certificates (`BEGIN CERTIFICATE`).");
+    }
+
+    let key = private_key(&mut &key[..])?.context(
+        "Could not locate a private key, tried PKCS#8 (`BEGIN PRIVATE KEY`), \
+        PKCS#1 (`BEGIN RSA PRIVATE KEY`) and SEC1 (`BEGIN EC PRIVATE KEY`).",
+    )?;
+
+    let signing_key = ring::sign::any_supported_type(&key)?;
+
+    Ok(CertifiedKey::new(cert_chain, signing_key))
+}
+
+/// Load a certificate chain and private key from PEM files.
+pub fn load_certified_key(cert_path: &Path, key_path: &Path) -> Result<CertifiedKey> {
+    let cert_chain = fs::read(cert_path)
+        .with_context(|| format!("Failed to read file {:?}", cert_path.to_string_lossy()))?;
+
+    let key = fs::read(key_path)
+        .with_context(|| format!("Failed to read file {:?}", key_path.to_string_lossy()))?;
+
+    certified_key_from_pem(&cert_chain, &key).with_context(|| {
+        format!(
+            "Failed to load TLS key {:?} and cert {:?}",
+            key_path.to_string_lossy(),
+            cert_path.to_string_lossy()
+        )
+    })
+}
+
+/// Staple the OCSP response, read from the `ocsp_response` file or fetched from the responder.
+///
+/// Stapling is best effort, if the response can't be read or fetched (or has expired) the
+/// previous response of the same certificate is stapled until it expires, otherwise the
+/// certificate is served without one.
+pub fn staple_ocsp(
+    certified_key: &mut CertifiedKey,
+    cert_path: &Path,
+    response_path: &Path,
+    ocsp: &Ocsp,
+    previous: Option<Arc<CertifiedKey>>,
+) {
+    let response = if !response_path.as_os_str().is_empty() {
+        ocsp::read_response(response_path, &certified_key.cert)
+    } else if ocsp.enable {
+        ocsp::fetch_response(&certified_key.cert)
+    } else {
+        return;
+    };
+
+    match response {
+        Ok(response) => certified_key.ocsp = Some(response),
+        Err(e) => {
+            let previous_response = previous
+                .filter(|previous| previous.cert == certified_key.cert)
+                .and_then(|previous| previous.ocsp.to_owned())
+                .filter(|response| ocsp::check_response(response, &certified_key.cert).is_ok());
+
+            let keeping = if previous_response.is_some() {
+                ", stapling the previous one until it expires"
+            } else {
+                ""
+            };
+
+            push_message(
+                Type::Error,
+                &format!(
+                    "Failed to staple the OCSP response of {:?}{}: {:#}",
+                    cert_path.to_string_lossy(),
+                    keeping,
+                    e
+                ),
+            );
+
+            certified_key.ocsp = previous_response;
+        }
+    }
+}
+
+/// Whether the default certificate is loaded from the `key`/`cert` files.
+///
+/// It's managed by the ACME client if enabled, and is optional when
+/// certificates are configured per server name.
+fn has_default_certificate(tls: &Tls) -> bool {
+    !tls.acme.enable && (tls.certificates.is_empty() || !tls.cert.as_os_str().is_empty())
+}
+
+/// The key/cert (and OCSP response) files of the configured certificates.
+pub fn certificate_files(tls: &Tls) -> Vec<PathBuf> {
+    let mut files = Vec::with_capacity((tls.certificates.len() + 1) * 3);
+
+    if has_default_certificate(tls) {
+        files.push(tls.cert.to_owned());
+        files.push(tls.key.to_owned());
+        files.push(tls.ocsp_response.to_owned());
+    }
+
+    for certificate in tls.certificates.values() {
+        files.push(certificate.cert.to_owned());
+        files.push(certificate.key.to_owned());
+        files.push(certificate.ocsp_response.to_owned());
+    }
+
+    files.retain(|file| !file.as_os_str().is_empty());
+
+    files
+}
+
+/// Load the configured certificates into the certificate resolver.
+///
+/// All of the certificates are parsed before swapping any of them in,
+/// so the current certificates are kept if one of them fails to load.
+pub fn load_certificates(tls: &Tls) -> Result<()> {
+    let mut default = None;
+
+    if has_default_certificate(tls) {
+        let mut certified_key = load_certified_key(&tls.cert, &tls.key)?;
+        staple_ocsp(
+            &mut certified_key,
+            &tls.cert,
+            &tls.ocsp_response,
+            &tls.ocsp,
+            CERT_RESOLVER.default_certificate(),
+        );
+
+        default = Some(certified_key);
+    }
+
+    let mut server_names = HashMap::with_capacity(tls.certificates.len());
+
+    for (server_name, certificate) in &tls.certificates {
+        let server_name = server_name.to_ascii_lowercase();
+
+        let mut certified_key = load_certified_key(&certificate.cert, &certificate.key)?;
+        staple_ocsp(
+            &mut certified_key,
+            &certificate.cert,
+            &certificate.ocsp_response,
+            &tls.ocsp,
+            CERT_RESOLVER.server_name_certificate(&server_name),
+        );
+
+        server_names.insert(server_name.into(), Arc::new(certified_key));
+    }
+
+    if let Some(certified_key) = default {
+        CERT_RESOLVER.set(certified_key);
+    }
+
+    CERT_RESOLVER.set_server_names(server_names);
+
+    Ok(())
+}
+
+/// Read the certificates of a CA bundle, each rustls version builds its own root store from them.
+pub fn read_ca_certificates(ca_path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let ca_bundle = fs::read(ca_path)
+        .with_context(|| format!("Failed to read file {:?}", ca_path.to_string_lossy()))?;
+
+    let ca_certs = certs(&mut &ca_bundle[..]).collect::<Result<Vec<_>, _>>()?;
+
+    if ca_certs.is_empty() {
+        bail!(
+            "Could not locate any CA certificates (`BEGIN CERTIFICATE`) in {:?}",
+            ca_path.to_string_lossy()
+        );
+    }
+
+    Ok(ca_certs)
+}
+
+/// Build the verifier of client certificates signed by the configured CA bundle.
+fn client_cert_verifier(client_auth: &ClientAuth) -> Result<Arc<dyn ClientCertVerifier>> {
+    let mut roots = RootCertStore::empty();
+    for cert in read_ca_certificates(&client_auth.ca)? {
+        roots.add(cert)?;
+    }
+
+    let mut verifier = WebPkiClientVerifier::builder(Arc::new(roots));
+
+    // let clients without a certificate through, the routes requiring
+    // a certificate are enforced per request instead
+    if client_auth.mode == ClientAuthMode::Optional {
+        verifier = verifier.allow_unauthenticated();
+    }
+
+    Ok(verifier.build()?)
+}
+
+/// The verified client certificate of a connection (mutual TLS)
+#[derive(Debug, Clone)]
+pub struct ClientCertificate {
+    pub subject: String,
+}
+
+/// Store the verified client certificate in the connection data. (registered with `on_connect`)
+///
+/// rustls only keeps the peer certificates once they have been verified.
+pub fn on_connect(connection: &dyn Any, data: &mut Extensions) {
+    let Some(tls_stream) = connection.downcast_ref::<TlsStream<TcpStream>>() else {
+        return;
+    };
+
+    let (_, session) = tls_stream.get_ref();
+
+    if let Some(cert) = session.peer_certificates().and_then(|certs| certs.first()) {
+        if let Ok((_, cert)) = X509Certificate::from_der(cert) {
+            data.insert(ClientCertificate {
+                subject: cert.subject().to_string(),
+            });
+        }
+    }
+}
+
+/// Whether the request path is one of the routes requiring a client certificate.
+///
+/// A route covers itself and everything under it, `/admin` matches `/admin/users`.
+/// The path is matched as it's requested and as it's resolved to a file, so encoded
+/// paths like `/%61dmin` or `/public/../admin` are covered as well.
+pub fn requires_client_certificate(routes: &[String], path: &str) -> bool {
+    let normalized_path = routes::normalize_path(path);
+
+    routes.iter().any(|route| {
+        let route = route.trim_end_matches('/');
+
+        [path, normalized_path.as_str()]
+            .iter()
+            .any(|path| match path.strip_prefix(route) {
+                Some(rest) => rest.is_empty() || rest.starts_with('/'),
+                None => false,
+            })
+    })
+}
+
+/// The protocol versions between `min_version` and `max_version`.
+fn protocol_versions(tls: &Tls) -> Result<Vec<&'static SupportedProtocolVersion>> {
+    let min_version = tls.min_version.unwrap_or(TlsVersion::Tls12);
+    let max_version = tls.max_version.unwrap_or(TlsVersion::Tls13);
+
+    if min_version > max_version {
+        bail!("Invalid TLS version range, `min_version` is higher than `max_version`");
+    }
+
+    Ok(rustls::ALL_VERSIONS
+        .iter()
+        .copied()
+        .filter(|supported| {
+            let version = match supported.version {
+                ProtocolVersion::TLSv1_2 => TlsVersion::Tls12,
+                _ => TlsVersion::Tls13,
+            };
+
+            (min_version..=max_version).contains(&version)
+        })
+        .collect())
+}
+
+/// The configured `cipher_suites` in order of preference, or the rustls defaults.
+fn cipher_suites(tls: &Tls) -> Result<Vec<SupportedCipherSuite>> {
+    if tls.cipher_suites.is_empty() {
+        return Ok(ring::DEFAULT_CIPHER_SUITES.to_vec());
+    }
+
+    let name_of = |suite: &SupportedCipherSuite| suite.suite().as_str().unwrap_or_default();
+
+    tls.cipher_suites
+        .iter()
+        .map(|name| {
+            ring::ALL_CIPHER_SUITES
+                .iter()
+                .find(|suite| name_of(suite).eq_ignore_ascii_case(name))
+                .copied()
+                .with_context(|| {
+                    let supported = ring::ALL_CIPHER_SUITES
+                        .iter()
+                        .map(name_of)
+                        .collect::<Vec<&str>>();
+
+                    format!(
+                        "Unknown cipher suite {:?}, the supported ones are: {}",
+                        name,
+                        supported.join(", ")
+                    )
+                })
+        })
+        .collect()
+}
+
 /// Load TLS configuration
 pub fn load_rustls_config() -> Result<rustls::ServerConfig> {
-    let config_state = &*CONFIG_STATE.lock();
+    // don't hold the config lock while the OCSP responders are queried
+    let tls = &CONFIG_STATE.lock().server.tls.to_owned();
+    let client_auth = &tls.client_auth;
+
+    // the HTTP server always offers `h2` and `http/1.1` ahead of the configured protocols,
+    // and treats any other negotiated protocol as HTTP/1.1
+    if !tls.alpn.is_empty() {
+        bail!(
+            "The `alpn` protocols can't be configured, binserve always offers `h2` and `http/1.1` and speaks no others"
+        );
+    }
+
+    // restrict the protocol versions and cipher suites to the configured policy
+    let provider = CryptoProvider {
+        cipher_suites: cipher_suites(tls)?,
+        ..ring::default_provider()
+    };
 
-    // init server config builder with safe defaults
-    let config = ServerConfig::builder().with_no_client_auth();
+    let config = ServerConfig::builder_with_provider(Arc::new(provider))
+        .with_protocol_versions(&protocol_versions(tls)?)
+        .context("None of the `cipher_suites` are usable with the allowed TLS versions")?;
 
-    let cert_file_path = &config_state.server.tls.cert;
-    let cert_key_path = &config_state.server.tls.key;
+    let config = if client_auth.enable {
+        config.with_client_cert_verifier(client_cert_verifier(client_auth)?)
+    } else {
+        config.with_no_client_auth()
+    };
 
     // load TLS key/cert files
-    let cert_file =
-        &mut BufReader::new(File::open(cert_file_path).with_context(|| {
-            format!("Failed to read file {:?}", cert_file_path.to_string_lossy())
-        })?);
+    load_certificates(tls)?;
 
-    let key_file =
-        &mut BufReader::new(File::open(cert_key_path).with_context(|| {
-            format!("Failed to read file {:?}", cert_key_path.to_string_lossy())
-        })?);
+    Ok(config.with_cert_resolver(CERT_RESOLVER.clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn protects_the_routes_and_everything_under_them() {
+        let routes = ["/admin".to_string(), "/static/private/".to_string()];
 
-    // convert files to key/cert objects
-    let cert_chain = certs(cert_file).collect::<Result<Vec<_>, _>>()?;
-    let key = pkcs8_private_keys(key_file).find_map(Result::ok);
+        assert!(requires_client_certificate(&routes, "/admin"));
+        assert!(requires_client_certificate(&routes, "/admin/users"));
+        assert!(requires_client_certificate(
+            &routes,
+            "/static/private/key.txt"
+        ));
 
-    // exit if no keys could be parsed
-    if key.is_none() {
-        push_message(Type::Error, "Could not locate PKCS 8 private keys.");
-        std::process::exit(1);
+        assert!(!requires_client_certificate(&routes, "/administrator"));
+        assert!(!requires_client_certificate(&routes, "/static/public.txt"));
     }
 
-    Ok(config.with_single_cert(cert_chain, key.unwrap().into())?)
+    #[test]
+    fn protects_the_encoded_paths() {
+        let routes = ["/static/admin".to_string()];
+
+        // as resolved by the static file service
+        assert!(requires_client_certificate(
+            &routes,
+            "/static/%61dmin/secret"
+        ));
+        assert!(requires_client_certificate(
+            &routes,
+            "/static/%61%64%6D%69%6E"
+        ));
+        assert!(requires_client_certificate(
+            &routes,
+            "//static//admin/secret"
+        ));
+        assert!(requires_client_certificate(
+            &routes,
+            "/static/./admin/secret"
+        ));
+        assert!(requires_client_certificate(
+            &routes,
+            "/static/public/../admin/secret"
+        ));
+        assert!(requires_client_certificate(
+            &routes,
+            "/static%2Fadmin/secret"
+        ));
+
+        assert!(!requires_client_certificate(
+            &routes,
+            "/static/%62dmin/secret"
+        ));
+    }
 }
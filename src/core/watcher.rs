@@ -1,23 +1,343 @@
-use notify_debouncer_mini::{new_debouncer, notify::RecursiveMode};
+use notify_debouncer_mini::{
+    new_debouncer,
+    notify::{RecursiveMode, Watcher},
+    DebouncedEvent,
+};
 
 use compact_str::CompactString;
 
-use std::collections::HashMap;
-use std::fs;
-use std::path::{Path, PathBuf};
-use std::sync::mpsc::channel;
-use std::time::Duration;
+use std::collections::{HashMap, HashSet};
+use std::path::{self, PathBuf};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::time::{Duration, Instant};
 
 use super::config::{BinserveConfig, CONFIG_FILE};
-use super::routes::{RouteHandle, Type, ROUTEMAP};
-use super::templates;
+use super::routes::{split_route_key, RouteHandle, Type, ROUTEMAP};
+use super::{assets, templates, tls};
+
+use crate::cli::messages::{push_message, Type as MsgType};
+
+/// Reload the TLS certificates, the current ones are kept if the new ones fail to load.
+fn reload_certificates(config: &BinserveConfig) {
+    match tls::load_certificates(&config.server.tls) {
+        Ok(()) => push_message(MsgType::Info, "Reloaded TLS certificates 🔒"),
+        Err(e) => push_message(
+            MsgType::Error,
+            &format!(
+                "Failed to reload TLS certificates, keeping the current ones: {:#}",
+                e
+            ),
+        ),
+    }
+}
+
+/// Refresh the stapled OCSP responses by reloading the certificates.
+fn refresh_ocsp_responses(config: &BinserveConfig) {
+    if let Err(e) = tls::load_certificates(&config.server.tls) {
+        push_message(
+            MsgType::Error,
+            &format!("Failed to refresh the OCSP responses: {:#}", e),
+        );
+    }
+}
+
+/// Report a failed hot reload, the watcher keeps going with the current state.
+fn report(what: &str, result: anyhow::Result<()>) {
+    if let Err(e) = result {
+        push_message(
+            MsgType::Error,
+            &format!("Failed to hot reload {}: {:#}", what, e),
+        );
+    }
+}
+
+/// The paths watched for changes, built again whenever the configuration changes
+/// as the sites, routes and certificates might have changed along with it.
+#[derive(Default)]
+struct WatchList {
+    // the watched files and directories
+    paths: HashMap<PathBuf, RecursiveMode>,
+    // file -> the routes (and their file) to reload when it changes
+    file_mapping: HashMap<PathBuf, Vec<(CompactString, PathBuf)>>,
+    // the templates refer to the static files with `{{asset}}` and `{{sri}}`, and the
+    // fingerprinted ones change names, so they're built again along with the templates
+    static_directories: Vec<(PathBuf, CompactString)>,
+    // the TLS key/cert files
+    tls_files: HashSet<PathBuf>,
+}
+
+impl WatchList {
+    fn new(config_state: &BinserveConfig) -> anyhow::Result<Self> {
+        let mut watch_list = Self {
+            file_mapping: HashMap::with_capacity(ROUTEMAP.len()),
+            ..Self::default()
+        };
+
+        // the binserve config file
+        watch_list.watch(path::absolute(CONFIG_FILE)?, RecursiveMode::NonRecursive);
+
+        // the TLS key/cert files are renewed by replacing them (or the symlinks to them, like
+        // certbot's `live/` ones), so their directories are watched rather than the files
+        if config_state.server.tls.enable {
+            for file_path in tls::certificate_files(&config_state.server.tls) {
+                let abs_file_path = path::absolute(file_path)?;
+
+                if let Some(directory) = abs_file_path.parent() {
+                    watch_list.watch(directory.to_path_buf(), RecursiveMode::NonRecursive);
+                }
+
+                watch_list.tls_files.insert(abs_file_path);
+            }
+        }
+
+        let default_site = config_state.default_site();
+        let sites = config_state
+            .sites
+            .iter()
+            .map(|(name, site)| (name.as_str(), site));
+
+        for (site_name, site) in std::iter::once(("", &default_site)).chain(sites) {
+            let static_directory = &site.r#static.directory;
+
+            if !site.r#static.served_from.is_empty() && static_directory.is_dir() {
+                let abs_static_directory = path::absolute(static_directory)?;
+
+                watch_list.watch(abs_static_directory.to_owned(), RecursiveMode::Recursive);
+                watch_list
+                    .static_directories
+                    .push((abs_static_directory, site_name.into()));
+            }
+        }
+
+        for route in ROUTEMAP.iter() {
+            let handler = route.value();
+
+            // with a memory budget, any of the routes might get cached
+            if handler.r#type == Type::Bytes
+                || handler.response.built_bytes().is_some()
+                || config_state.cache.max_memory.is_some()
+            {
+                let key = route.key();
+                let file_path = &handler.response.path;
+
+                if *file_path == PathBuf::new() {
+                    continue;
+                }
+
+                // the precompressed variants are reloaded along with the file
+                let variant_paths = handler
+                    .response
+                    .variants
+                    .iter()
+                    .map(|v| &v.path)
+                    .filter(|path| !path.as_os_str().is_empty());
+
+                for watched_path in std::iter::once(file_path).chain(variant_paths) {
+                    // the events are reported by the watched path (not where its symlinks lead)
+                    let abs_file_path = path::absolute(watched_path)?;
+
+                    watch_list.watch(abs_file_path.to_owned(), RecursiveMode::Recursive);
+
+                    // map them to the corresponding keys in the routemap
+                    watch_list
+                        .file_mapping
+                        .entry(abs_file_path)
+                        .or_default()
+                        .push((key.to_owned(), file_path.to_owned()));
+                }
+            }
+        }
+
+        Ok(watch_list)
+    }
+
+    /// Add a path to the list, watching a directory recursively takes precedence.
+    fn watch(&mut self, path: PathBuf, recursive_mode: RecursiveMode) {
+        let mode = self.paths.entry(path).or_insert(recursive_mode);
+
+        if recursive_mode == RecursiveMode::Recursive {
+            *mode = recursive_mode;
+        }
+    }
+
+    /// Watch the paths which are new to the list and stop watching the ones which aren't in it anymore.
+    ///
+    /// A path which can't be watched (like a missing file) doesn't stop the others from being watched.
+    fn apply(&self, previous: &Self, watcher: &mut dyn Watcher) {
+        for (removed_path, mode) in &previous.paths {
+            if self.paths.get(removed_path) != Some(mode) {
+                // it might be gone already
+                let _ = watcher.unwatch(removed_path);
+            }
+        }
+
+        for (added_path, mode) in &self.paths {
+            if previous.paths.get(added_path) == Some(mode) {
+                continue;
+            }
+
+            // add to the system filesystem events watch list
+            if let Err(e) = watcher.watch(added_path, *mode) {
+                push_message(
+                    MsgType::Error,
+                    &format!(
+                        "Failed to watch {:?} for changes: {}",
+                        added_path.to_string_lossy(),
+                        e
+                    ),
+                );
+            }
+        }
+    }
+}
+
+/// Reload the route served from a file which has changed.
+fn reload_route(route_key: &str, file_path: &PathBuf) -> anyhow::Result<()> {
+    // read the configuration file
+    let config = BinserveConfig::read()?;
+
+    let (site_name, route) = split_route_key(route_key);
+
+    // the site might have been removed from the configuration
+    let Some(site) = config.site(site_name) else {
+        return Ok(());
+    };
+
+    // the error pages aren't routes, the templates are rendered per request
+    if route.starts_with("{{") {
+        return RouteHandle::add_error_pages(site_name, &site.r#static, &config.config);
+    }
+
+    // prepare template partials
+    let handlebars_handle =
+        templates::render_templates(&site.template, assets::site_assets(site_name))?;
+
+    // reload the file state and update the global program state
+    RouteHandle::associate_files_to_routes(site_name, route, file_path, &handlebars_handle)?;
+
+    Ok(())
+}
+
+/// Collect the static files of a site again, along with its templates.
+fn reload_site_assets(site_name: &str) -> anyhow::Result<()> {
+    let config = BinserveConfig::read()?;
+
+    // the site might have been removed from the configuration
+    match config.site(site_name) {
+        Some(site) => RouteHandle::reload_assets(site_name, &site, &config.config),
+        None => Ok(()),
+    }
+}
+
+/// The state of the hot reloader, as of the last configuration it has loaded.
+struct HotReloader {
+    config_state: BinserveConfig,
+    watch_list: WatchList,
+}
+
+impl HotReloader {
+    /// Read the configuration file again and create the routes (and the watch list) with it.
+    fn reload_config(&mut self, watcher: &mut dyn Watcher) -> anyhow::Result<()> {
+        // read the configuration file
+        let config = BinserveConfig::read()?;
+
+        // prepare template partials and routes tables
+        RouteHandle::add_sites(&config)?;
+
+        // the sites, routes and certificates might have been added or removed
+        let watch_list = WatchList::new(&config)?;
+        watch_list.apply(&self.watch_list, watcher);
+
+        self.watch_list = watch_list;
+        self.config_state = config;
+
+        Ok(())
+    }
+
+    /// Reload the state changed by a batch of filesystem events.
+    fn handle_events(&mut self, events: Vec<DebouncedEvent>, watcher: &mut dyn Watcher) {
+        let abs_config_path = path::absolute(CONFIG_FILE).unwrap_or_default();
+
+        // the key and cert are usually replaced together,
+        // reload them once after the whole batch of events
+        let mut tls_files_changed = false;
+
+        // the same goes for the static files of the sites
+        let mut assets_changed: HashSet<CompactString> = HashSet::new();
+
+        // the routes are created again along with the configuration
+        let mut config_changed = false;
+
+        for event in events {
+            if event.path == abs_config_path {
+                config_changed = true;
+                continue;
+            }
+
+            tls_files_changed |= self.watch_list.tls_files.contains(&event.path);
+
+            assets_changed.extend(
+                self.watch_list
+                    .static_directories
+                    .iter()
+                    .filter(|(directory, _)| event.path.starts_with(directory))
+                    .map(|(_, site_name)| site_name.to_owned()),
+            );
+
+            for (route_key, file_path) in self
+                .watch_list
+                .file_mapping
+                .get(&event.path)
+                .into_iter()
+                .flatten()
+            {
+                report(
+                    &format!("{:?}", file_path.to_string_lossy()),
+                    reload_route(route_key, file_path),
+                );
+            }
+        }
+
+        if config_changed {
+            match self.reload_config(watcher) {
+                Ok(()) => {
+                    // the static files are collected along with the sites
+                    assets_changed.clear();
+
+                    tls_files_changed |= self.config_state.server.tls.enable;
+                }
+                Err(e) => report(CONFIG_FILE, Err(e)),
+            }
+        }
+
+        for site_name in &assets_changed {
+            report("the static files", reload_site_assets(site_name));
+        }
+
+        if tls_files_changed {
+            reload_certificates(&self.config_state);
+        }
+    }
+}
 
 /// Watch for filesystem for updates/writes and hot reload the server state.
-pub fn hot_reload_files() -> anyhow::Result<()> {
-    let config_state = BinserveConfig::read()?;
+///
+/// It starts out with the configuration the server was started with (command-line overrides included).
+pub fn hot_reload_files(config_state: BinserveConfig) -> anyhow::Result<()> {
+    // OCSP responses expire, fetch them again periodically
+    let tls = &config_state.server.tls;
+    let ocsp_refresh_interval =
+        (tls.enable && tls.ocsp.enable).then(|| Duration::from_secs(tls.ocsp.refresh_interval));
 
     // check if hot reload is enabled or not
     if !config_state.config.enable_hot_reload {
+        if let Some(refresh_interval) = ocsp_refresh_interval {
+            loop {
+                std::thread::sleep(refresh_interval);
+                refresh_ocsp_responses(&config_state);
+            }
+        }
+
         return Ok(());
     }
 
@@ -28,76 +348,41 @@ pub fn hot_reload_files() -> anyhow::Result<()> {
     // The notification back-end is selected based on the platform.
     let mut debouncer = new_debouncer(Duration::from_secs(1), tx)?;
 
-    let mut file_mapping: HashMap<PathBuf, CompactString> = HashMap::with_capacity(ROUTEMAP.len());
-
-    // add the binserve config file to the hot reloader
-    let config_file_path = PathBuf::from(CONFIG_FILE);
-    let abs_config_path = fs::canonicalize(config_file_path)?;
-    debouncer
-        .watcher()
-        .watch(Path::new(CONFIG_FILE), RecursiveMode::Recursive)?;
-
     // Add a path to be watched. All files and directories at that path and
     // below will be monitored for changes.
-    for route in ROUTEMAP.iter() {
-        let handler = route.value();
+    let watch_list = WatchList::new(&config_state)?;
+    watch_list.apply(&WatchList::default(), debouncer.watcher());
 
-        if handler.r#type == Type::Bytes {
-            let key = route.key();
-            let file_path = &handler.response.path;
+    let mut hot_reloader = HotReloader {
+        config_state,
+        watch_list,
+    };
 
-            if *file_path == PathBuf::new() {
-                continue;
-            }
-
-            let abs_file_path = fs::canonicalize(file_path)?;
+    let mut next_ocsp_refresh = ocsp_refresh_interval.map(|interval| Instant::now() + interval);
 
-            // add to the system filesystem events watch list
-            debouncer
-                .watcher()
-                .watch(file_path, RecursiveMode::Recursive)?;
+    loop {
+        let events = match next_ocsp_refresh {
+            Some(next_refresh) => {
+                rx.recv_timeout(next_refresh.saturating_duration_since(Instant::now()))
+            }
+            None => rx.recv().map_err(RecvTimeoutError::from),
+        };
 
-            // map them to the corresponding keys in the routemap
-            file_mapping.insert(abs_file_path, key.to_owned());
-        }
-    }
+        match events {
+            Ok(Ok(events)) => hot_reloader.handle_events(events, debouncer.watcher()),
+            Err(RecvTimeoutError::Timeout) => {
+                refresh_ocsp_responses(&hot_reloader.config_state);
 
-    loop {
-        match rx.recv() {
-            Ok(Ok(events)) => {
-                for event in events {
-                    if event.path == abs_config_path {
-                        // read the configuration file
-                        let config = BinserveConfig::read()?;
-
-                        // prepare template partials
-                        let handlebars_handle = templates::render_templates(&config)?;
-
-                        // prepare routes table
-                        RouteHandle::add_routes(&config.routes, &handlebars_handle)?;
-                    }
-
-                    if let Some(route_key) = file_mapping.get(&event.path) {
-                        // read the configuration file
-                        let config = BinserveConfig::read()?;
-
-                        // prepare template partials
-                        let handlebars_handle = templates::render_templates(&config)?;
-
-                        // reload the file state and update the global program state
-                        RouteHandle::associate_files_to_routes(
-                            &route_key.to_string(),
-                            &event.path,
-                            &handlebars_handle,
-                        )?;
-                    }
-                }
+                next_ocsp_refresh = ocsp_refresh_interval.map(|interval| Instant::now() + interval);
             }
             Err(e) => {
                 println!(
                     "[!] filesystem watch channel error (binserve hot reload): {:?}",
                     e
-                )
+                );
+
+                // the watcher is gone, there's nothing left to receive
+                return Ok(());
             }
             Ok(Err(e)) => {
                 println!("[!] filesystem watch error (binserve hot reload): {:?}", e)